@@ -1,11 +1,21 @@
-use crate::cli::config::get_config_dir;
-use crate::elasticnow::servicenow_structs::SysIdResult;
+use crate::cli::config::{get_config_dir, parse_profile_name, AuthMethod};
+use crate::cli::credentials::CredentialStore;
+use crate::cli::picker::{self, PickerItem};
+use crate::elasticnow::servicenow::TIME_FORMAT_HELP;
+use crate::elasticnow::servicenow_structs::{Choice, SysIdResult};
 use ansi_term::Colour;
-use chrono::{Datelike, Duration, Local};
-use clap::{Command, CommandFactory, Parser, Subcommand};
+use chrono::{Datelike, Duration, Local, NaiveDate};
+use clap::{Command, CommandFactory, Parser, Subcommand, ValueEnum};
 use clap_complete::{generate, Generator, Shell};
 use dialoguer::{theme::ColorfulTheme, Select};
+use serde::Serialize;
+use std::path::PathBuf;
 use std::{collections::HashMap, io};
+use textplots::{Chart, LabelBuilder, LabelFormat, Plot, Shape};
+
+// Reports under these totals are highlighted in red
+pub const WEEKLY_TARGET_HOURS: f64 = 32.0;
+pub const DEFAULT_DAILY_TARGET_HOURS: f64 = WEEKLY_TARGET_HOURS / 5.0;
 
 #[derive(Parser)]
 #[command(name = "elasticnow", about = "ElasticNow time tracking CLI", version)]
@@ -14,6 +24,10 @@ pub struct Args {
     #[arg(long = "generate", value_enum)]
     generator: Option<Shell>,
 
+    /// Profile in config.toml to use (defaults to default_profile in config.toml)
+    #[arg(long, global = true, env = "ELASTICNOW_PROFILE", value_parser = parse_profile_name)]
+    pub profile: Option<String>,
+
     #[command(subcommand)]
     pub cmd: Option<Commands>,
 }
@@ -35,10 +49,10 @@ pub enum Commands {
         #[clap(
             short,
             long,
-            help = format!("Add time in the format of {} where 1 can be replaced with any number (hours must be less than 20)", Colour::Green.bold().paint("1h1m")))
+            help = format!("Add time in the format of {} or {} ({})", Colour::Green.bold().paint("1h1m"), Colour::Green.bold().paint("1.5h"), TIME_FORMAT_HELP))
         ]
         time_worked: String,
-        #[clap(short, long, required_unless_present_any = ["new", "no_tkt", "all"])]
+        #[clap(short, long, required_unless_present_any = ["new", "no_tkt", "all", "ticket", "sys_id", "recent", "split"])]
         /// Keyword search using ElasticNow (returns all tickets in bin by default)
         search: Option<String>,
         #[clap(short, long, visible_alias = "assignment-group")]
@@ -48,6 +62,33 @@ pub enum Commands {
         #[clap(long, conflicts_with_all = ["search","new"], action = clap::ArgAction::SetTrue)]
         /// Uses timetracking without a ticket
         no_tkt: bool,
+        #[clap(long, requires = "no_tkt")]
+        /// Category for --no-tkt by value or label instead of prompting (e.g. clerical)
+        category: Option<String>,
+        #[clap(long, conflicts_with_all = ["search", "new", "all", "no_tkt", "sys_id"])]
+        /// Ticket number to track the time on without searching (e.g. RITM0012345)
+        ticket: Option<String>,
+        #[clap(long, conflicts_with_all = ["search", "new", "all", "no_tkt"])]
+        /// sys_id of the ticket to track the time on without searching
+        sys_id: Option<String>,
+        #[clap(long, conflicts_with_all = ["new", "no_tkt"], action = clap::ArgAction::SetTrue)]
+        /// Use the first ticket found by --search or --all instead of prompting
+        pick_first: bool,
+        #[clap(short, long, conflicts_with_all = ["search", "new", "all", "no_tkt", "ticket", "sys_id", "pick_first"], action = clap::ArgAction::SetTrue)]
+        /// Choose from favourite and recently used tickets without searching
+        recent: bool,
+        #[clap(
+            long,
+            conflicts_with_all = ["search", "new", "all", "no_tkt", "ticket", "sys_id", "pick_first", "recent"],
+            help = format!("Split the time across tickets and no-ticket categories by duration or percentage (e.g. {})", Colour::Green.bold().paint("RITM0012345=50%,RITM0012346=25%,clerical=25%"))
+        )]
+        split: Option<String>,
+
+        #[clap(short, long, help = format!("Day to track the time for in {} format (defaults to today)", Colour::Green.bold().paint("YYYY-MM-DD")))]
+        date: Option<String>,
+        #[clap(short = 'y', long, conflicts_with = "date", action = clap::ArgAction::SetTrue)]
+        /// Track the time for yesterday
+        yesterday: bool,
     },
     /// Get time tracking report showing hours worked and benefitting departments.
     Report {
@@ -66,6 +107,21 @@ pub enum Commands {
         #[clap(short, long, default_value = "10")]
         /// Limit the number of cost centers returned in the report. Any extra fields will be grouped into other
         top: Option<usize>,
+
+        #[clap(short, long, value_enum, default_value_t = ReportFormat::Table)]
+        /// Output format of the report
+        format: ReportFormat,
+
+        #[clap(short, long, conflicts_with = "by", action = clap::ArgAction::SetTrue)]
+        /// Show time worked per day with a chart instead of grouping by cost center (same as --by day)
+        daily: bool,
+
+        #[clap(long, value_enum, default_value_t = ReportBy::CostCenter)]
+        /// Group time worked by ticket, no-ticket category, cost center or day
+        by: ReportBy,
+
+        #[clap(long, requires = "daily", help = format!("Hours per day below which weekdays are highlighted in --daily (defaults to daily_target in config.toml or {})", DEFAULT_DAILY_TARGET_HOURS))]
+        target: Option<f64>,
     },
 
     /// Create a std chg using a template
@@ -82,7 +138,43 @@ pub enum Commands {
         template_id: Option<String>,
     },
 
-    #[clap(about = format!("Create a new config file in {}", get_config_dir().display()))]
+    /// Retry time entries that were queued while ServiceNow was unreachable
+    Sync,
+
+    /// Track time with a timer that persists between sessions instead of typing durations
+    Timer {
+        #[command(subcommand)]
+        cmd: TimerCommands,
+    },
+
+    /// Pin tickets to the top of the ticket picker
+    Fav {
+        #[command(subcommand)]
+        cmd: FavCommands,
+    },
+
+    /// Track time from a CSV or JSON timesheet with date, ticket or category, duration and comment
+    Import {
+        /// CSV file with a header row, or a .json array of objects
+        file: PathBuf,
+        #[clap(short, long)]
+        /// Where to write the result of each row (defaults to FILE.results.csv or .json next to FILE)
+        output: Option<PathBuf>,
+        #[clap(long, action = clap::ArgAction::SetTrue)]
+        /// Validate the rows and show the summary without posting anything
+        dry_run: bool,
+        #[clap(short, long, action = clap::ArgAction::SetTrue)]
+        /// Post without asking for confirmation
+        yes: bool,
+    },
+
+    /// Show, change or validate the current profile without running setup again
+    Config {
+        #[command(subcommand)]
+        cmd: ConfigCommands,
+    },
+
+    #[clap(about = format!("Create or update a profile (--profile) in {}", get_config_dir().join("config.toml").display()))]
     Setup {
         #[clap(long, env = "ELASTICNOW_ID", hide_env_values = true)]
         /// The ElasticNow ID (retrieved from ElasticNow instance)
@@ -91,20 +183,161 @@ pub enum Commands {
         /// The ElasticNow instance
         instance: String,
         #[clap(long, env = "SN_INSTANCE")]
-        /// The ServiceNow Instance (e.g. libertydev, liberty)
+        /// The ServiceNow Instance (e.g. libertydev, liberty) or base URL (e.g. https://sn.example.edu)
         sn_instance: String,
         #[clap(long, env = "SN_USERNAME")]
         /// The ServiceNow Username
         sn_username: String,
         #[clap(long, env = "SN_PASSWORD", hide_env_values = true)]
-        /// The ServiceNow Password
-        sn_password: String,
+        /// The ServiceNow Password (prompted for when needed and not set)
+        sn_password: Option<String>,
+        #[clap(long, value_enum)]
+        /// Where to keep the password and ElasticNow ID (prompted for when not set)
+        credential_store: Option<CredentialStore>,
+        #[clap(long)]
+        /// Command printing the ServiceNow password for the command store (e.g. "pass show sn")
+        password_command: Option<String>,
+        #[clap(long, value_enum)]
+        /// How requests to ServiceNow are authenticated (defaults to the profile's current method or basic)
+        auth: Option<AuthMethod>,
+        #[clap(long, env = "SN_OAUTH_CLIENT_ID")]
+        /// Client ID of the ServiceNow OAuth application (required for --auth oauth unless the profile has one)
+        oauth_client_id: Option<String>,
+        #[clap(long, env = "SN_OAUTH_CLIENT_SECRET", hide_env_values = true)]
+        /// Client secret of the ServiceNow OAuth application
+        oauth_client_secret: Option<String>,
         /// Override default bin for searching (defaults to user's assigned bin)
         #[clap(short, long)]
         bin: Option<String>,
     },
 }
 
+#[derive(Subcommand, Debug, Clone)]
+pub enum TimerCommands {
+    /// Start a new timer
+    Start {
+        #[clap(short, long)]
+        /// Comment for time tracking (can be overridden when stopping)
+        comment: Option<String>,
+        #[clap(short, long, action = clap::ArgAction::SetTrue)]
+        /// Replace a timer that is already running
+        force: bool,
+    },
+    /// Stop the running timer and add the elapsed time to a ticket
+    Stop {
+        #[clap(short, long, conflicts_with_all = ["search","no_tkt", "all"], action = clap::ArgAction::SetTrue)]
+        /// Creates a new ticket instead of updating an existing one ( cannot be used with --search )
+        new: bool,
+        #[clap(short, long, conflicts_with_all = ["search","no_tkt"], action = clap::ArgAction::SetTrue)]
+        /// Returns all item in the bin instead of searching
+        all: bool,
+        #[clap(short, long)]
+        /// Comment for time tracking (defaults to the comment given on start)
+        comment: Option<String>,
+        #[clap(short, long, required_unless_present_any = ["new", "no_tkt", "all", "ticket", "sys_id", "recent"])]
+        /// Keyword search using ElasticNow (returns all tickets in bin by default)
+        search: Option<String>,
+        #[clap(short, long, visible_alias = "assignment-group")]
+        /// Override default bin for searching (defaults to user's assigned bin or override in config.toml)
+        bin: Option<String>,
+        #[clap(long, conflicts_with_all = ["search","new"], action = clap::ArgAction::SetTrue)]
+        /// Uses timetracking without a ticket
+        no_tkt: bool,
+        #[clap(long, requires = "no_tkt")]
+        /// Category for --no-tkt by value or label instead of prompting (e.g. clerical)
+        category: Option<String>,
+        #[clap(long, conflicts_with_all = ["search", "new", "all", "no_tkt", "sys_id"])]
+        /// Ticket number to track the time on without searching (e.g. RITM0012345)
+        ticket: Option<String>,
+        #[clap(long, conflicts_with_all = ["search", "new", "all", "no_tkt"])]
+        /// sys_id of the ticket to track the time on without searching
+        sys_id: Option<String>,
+        #[clap(long, conflicts_with_all = ["new", "no_tkt"], action = clap::ArgAction::SetTrue)]
+        /// Use the first ticket found by --search or --all instead of prompting
+        pick_first: bool,
+        #[clap(short, long, conflicts_with_all = ["search", "new", "all", "no_tkt", "ticket", "sys_id", "pick_first"], action = clap::ArgAction::SetTrue)]
+        /// Choose from favourite and recently used tickets without searching
+        recent: bool,
+    },
+    /// Show the running timer
+    Status,
+    /// Discard the running timer without tracking time
+    Cancel,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum ConfigCommands {
+    /// Print the profile with secrets masked
+    Show,
+    /// Print a single value (e.g. bin or retry.max_attempts)
+    Get { key: String },
+    /// Change values, e.g. elasticnow config set bin=Linux daily_target=7.5
+    Set {
+        #[clap(required = true, value_parser = parse_assignment)]
+        /// KEY=VALUE pairs to change
+        values: Vec<(String, String)>,
+    },
+    /// Check the ServiceNow credentials, the bin and the ElasticNow ID
+    Validate,
+    /// Print the path of config.toml
+    Path,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum FavCommands {
+    /// Pin a ticket by number (e.g. RITM0012345)
+    Add { ticket: String },
+    /// Unpin a ticket by number or sys_id
+    Rm { ticket: String },
+    /// Show favourite and recently used tickets
+    List,
+}
+
+fn parse_assignment(value: &str) -> Result<(String, String), String> {
+    match value.split_once('=') {
+        Some((key, value)) => Ok((key.trim().to_string(), value.to_string())),
+        None => Err(format!("expected KEY=VALUE, got {}", value)),
+    }
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Default)]
+pub enum ReportFormat {
+    /// Coloured output for the terminal
+    #[default]
+    Table,
+    Json,
+    Csv,
+    Tsv,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Default)]
+pub enum ReportBy {
+    /// Ticket number and short description
+    Ticket,
+    /// No-ticket category, time worked on tickets is grouped together
+    Category,
+    /// Cost center benefitting from the ticket, or category for time without a ticket
+    #[default]
+    CostCenter,
+    /// Day the time was tracked for, with a chart
+    Day,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct ReportRow {
+    pub name: String,
+    pub seconds: i64,
+    pub duration: String,
+    pub percent: f64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Report {
+    pub rows: Vec<ReportRow>,
+    pub total_seconds: i64,
+    pub total_duration: String,
+}
+
 pub fn get_args() -> Args {
     let args = Args::parse();
     if let Some(shell) = args.generator {
@@ -115,16 +348,79 @@ pub fn get_args() -> Args {
     args
 }
 
-pub fn choose_options(mut options: Vec<String>) -> String {
-    options.append(&mut vec!["New ticket".to_string(), "Cancel".to_string()]);
+// Returns the label of the chosen ticket, "New ticket" or "Cancel"
+pub fn choose_options(mut options: Vec<PickerItem>) -> String {
+    options.append(&mut vec![
+        PickerItem::new("New ticket"),
+        PickerItem::new("Cancel"),
+    ]);
+    match picker::pick("Please choose a ticket (type to filter):", &options) {
+        Ok(Some(selection)) => options[selection].label.clone(),
+        Ok(None) => "Cancel".to_string(),
+        Err(e) => {
+            tracing::error!(
+                "Unable to choose a ticket: {}. Use --ticket, --sys-id or --pick-first instead",
+                e
+            );
+            std::process::exit(1);
+        }
+    }
+}
+
+// Asks before an import is posted, exiting when there is no terminal to ask in
+pub fn confirm_import() -> bool {
+    if !console::Term::stderr().is_term() {
+        tracing::error!("No terminal to confirm the import in. Use --yes to import without asking");
+        std::process::exit(1);
+    }
+    dialoguer::Confirm::with_theme(&ColorfulTheme::default())
+        .with_prompt("Post these entries?")
+        .default(false)
+        .interact()
+        .unwrap_or(false)
+}
+
+pub fn choose_credential_store() -> CredentialStore {
+    let options = [
+        (
+            CredentialStore::Config,
+            "Config file: stored in config.toml, readable only by you",
+        ),
+        (
+            CredentialStore::Command,
+            "Command: run a password manager command (e.g. pass show sn)",
+        ),
+        (
+            CredentialStore::Encrypted,
+            "Encrypted file: stored in secrets.enc, unlocked with a passphrase",
+        ),
+        (
+            CredentialStore::Env,
+            "Environment: read SN_PASSWORD and ELASTICNOW_ID, nothing is saved",
+        ),
+    ];
+    let items: Vec<&str> = options.iter().map(|(_, desc)| *desc).collect();
     let selection = Select::with_theme(&ColorfulTheme::default())
-        .with_prompt("Please choose a ticket:")
+        .with_prompt("Where should credentials be stored?")
         .default(0)
-        .items(&options)
+        .items(&items)
         .interact()
         .unwrap();
+    options[selection].0
+}
+
+pub fn write_password_command() -> String {
+    dialoguer::Input::<String>::with_theme(&ColorfulTheme::default())
+        .with_prompt("Command printing the ServiceNow password:")
+        .interact()
+        .unwrap()
+}
 
-    options[selection].to_string()
+pub fn write_password() -> String {
+    dialoguer::Password::with_theme(&ColorfulTheme::default())
+        .with_prompt("ServiceNow password")
+        .interact()
+        .unwrap()
 }
 
 pub fn write_short_description() -> String {
@@ -142,7 +438,7 @@ pub fn print_completions<G: Generator>(gen: G, cmd: &mut Command) {
 pub fn choose_chg_template(chg_templates: Vec<SysIdResult>) -> String {
     let options: Vec<String> = chg_templates
         .iter()
-        .map(|t| format!("{}", t.sys_name.as_ref().unwrap()))
+        .map(|t| t.sys_name.as_ref().unwrap().to_string())
         .collect();
 
     let selection = Select::with_theme(&ColorfulTheme::default())
@@ -155,13 +451,18 @@ pub fn choose_chg_template(chg_templates: Vec<SysIdResult>) -> String {
     chg_templates[selection].sys_id.clone()
 }
 
-pub fn choose_category() -> String {
-    let options = vec![
-        "Certs, Pro Dev, Training: Conferences, Studying or Taking Certifications, Webinars, On-boarding, or Employee to Employee Training", 
-        "University Events: Convocation, You Matter", 
-        "Clerical: Email, Operational Meetings, & Paperwork that cannot be tied to a task"
-    ];
-    let items = vec!["certs_prodev_training", "clerical", "univ_events"];
+// Returns the value of the chosen category
+pub fn choose_category(categories: &[Choice]) -> String {
+    let options: Vec<&str> = categories
+        .iter()
+        .map(|c| {
+            if c.hint.is_empty() {
+                c.label.as_str()
+            } else {
+                c.hint.as_str()
+            }
+        })
+        .collect();
     let selection = Select::with_theme(&ColorfulTheme::default())
         .with_prompt("Please choose a category for timetracking:")
         .default(0)
@@ -169,7 +470,7 @@ pub fn choose_category() -> String {
         .interact()
         .unwrap();
 
-    items[selection].to_string()
+    categories[selection].value.clone()
 }
 
 pub fn range_format_validate(date: &str) -> Result<(), Box<dyn std::error::Error>> {
@@ -180,13 +481,13 @@ pub fn range_format_validate(date: &str) -> Result<(), Box<dyn std::error::Error
     let year = date[0].parse::<i32>()?;
     let month = date[1].parse::<i32>()?;
     let day = date[2].parse::<i32>()?;
-    if year < 2000 || year > 3000 {
+    if !(2000..=3000).contains(&year) {
         return Err("Year must be between 2010 and 2100".into());
     }
-    if month < 1 || month > 12 {
+    if !(1..=12).contains(&month) {
         return Err("Month must be between 1 and 12".into());
     }
-    if day < 1 || day > 31 {
+    if !(1..=31).contains(&day) {
         return Err("Day must be between 1 and 31".into());
     }
     Ok(())
@@ -197,6 +498,16 @@ pub fn get_today() -> String {
     format!("{}-{:02}-{:02}", now.year(), now.month(), now.day())
 }
 
+pub fn get_yesterday() -> String {
+    let yesterday = Local::now() - Duration::days(1);
+    format!(
+        "{}-{:02}-{:02}",
+        yesterday.year(),
+        yesterday.month(),
+        yesterday.day()
+    )
+}
+
 pub fn get_week_start() -> String {
     let now = Local::now();
     let now = now - Duration::days(now.weekday().num_days_from_monday() as i64);
@@ -208,7 +519,7 @@ pub fn pretty_print_time_worked(time_worked: HashMap<String, i64>, top: usize, t
     let total_str = ansi_term::Colour::Blue.bold().paint("Total:").to_string();
     let top_ten = group_top_x(time_worked, top);
     let mut sorted_top_ten: Vec<_> = top_ten.into_iter().collect();
-    sorted_top_ten.sort_by(|a, b| a.1.cmp(&b.1));
+    sorted_top_ten.sort_by_key(|a| a.1);
     for (k, v) in sorted_top_ten {
         println!(
             "{}: {}",
@@ -216,7 +527,7 @@ pub fn pretty_print_time_worked(time_worked: HashMap<String, i64>, top: usize, t
             seconds_to_pretty(v)
         );
     }
-    if total < 3600 * 32 {
+    if total < (WEEKLY_TARGET_HOURS * 3600.0) as i64 {
         println!(
             "{}: {}",
             total_str,
@@ -231,6 +542,203 @@ pub fn pretty_print_time_worked(time_worked: HashMap<String, i64>, top: usize, t
     }
 }
 
+pub fn print_time_worked(
+    time_worked: HashMap<String, i64>,
+    top: usize,
+    total: i64,
+    format: ReportFormat,
+) {
+    if format == ReportFormat::Table {
+        pretty_print_time_worked(time_worked, top, total);
+        return;
+    }
+    print_structured_report(&build_report(time_worked, top, total), format);
+}
+
+fn print_structured_report(report: &Report, format: ReportFormat) {
+    match format {
+        ReportFormat::Json => println!(
+            "{}",
+            serde_json::to_string_pretty(report).unwrap_or_default()
+        ),
+        ReportFormat::Csv => print!("{}", report_to_delimited(report, ',')),
+        ReportFormat::Tsv => print!("{}", report_to_delimited(report, '\t')),
+        ReportFormat::Table => unreachable!(),
+    }
+}
+
+// Prints time worked for every day between since and until, weekdays under the target are red
+pub fn print_daily_time_worked(
+    time_per_day: HashMap<String, i64>,
+    since: &str,
+    until: &str,
+    target_hours: f64,
+    format: ReportFormat,
+) {
+    let days = days_in_range(since, until);
+    let total: i64 = time_per_day.values().sum();
+    if format != ReportFormat::Table {
+        print_structured_report(&build_daily_report(&time_per_day, &days, total), format);
+        return;
+    }
+    let target_seconds = (target_hours * 3600.0) as i64;
+    for day in &days {
+        let key = day.format("%Y-%m-%d").to_string();
+        let seconds = *time_per_day.get(&key).unwrap_or(&0);
+        let weekend = day.weekday().number_from_monday() > 5;
+        let duration = if weekend {
+            ansi_term::Style::default().paint(seconds_to_pretty(seconds))
+        } else if seconds < target_seconds {
+            ansi_term::Colour::Red
+                .bold()
+                .paint(seconds_to_pretty(seconds))
+        } else {
+            ansi_term::Colour::Green.paint(seconds_to_pretty(seconds))
+        };
+        println!(
+            "{}: {}",
+            ansi_term::Colour::Purple
+                .italic()
+                .paint(day.format("%Y-%m-%d %a").to_string()),
+            duration
+        );
+    }
+    println!(
+        "{}: {}",
+        ansi_term::Colour::Blue.bold().paint("Total:"),
+        seconds_to_pretty(total)
+    );
+    if days.is_empty() {
+        return;
+    }
+
+    // Bars are drawn between consecutive points using the height of the second point
+    let points: Vec<(f32, f32)> = std::iter::once((0.0, 0.0))
+        .chain(days.iter().enumerate().map(|(i, day)| {
+            let seconds = *time_per_day
+                .get(&day.format("%Y-%m-%d").to_string())
+                .unwrap_or(&0);
+            ((i + 1) as f32, seconds as f32 / 3600.0)
+        }))
+        .collect();
+    let max_hours = points
+        .iter()
+        .map(|p| p.1)
+        .fold(target_hours as f32, f32::max);
+    let first_day = days[0];
+    let target = target_hours as f32;
+    println!(
+        "Hours per day (target {} hours):",
+        ansi_term::Colour::Green.paint(target_hours.to_string())
+    );
+    // The bar of days[i] ends at x = i + 1, so the right label at x = days.len() is the last day
+    Chart::new_with_y_range(120, 40, 0.0, days.len() as f32, 0.0, max_hours * 1.1)
+        .x_label_format(LabelFormat::Custom(Box::new(move |x| {
+            (first_day + Duration::days((x as i64 - 1).max(0)))
+                .format("%Y-%m-%d")
+                .to_string()
+        })))
+        .lineplot(&Shape::Bars(&points))
+        .lineplot(&Shape::Continuous(Box::new(move |_| target)))
+        .display();
+}
+
+pub fn build_daily_report(
+    time_per_day: &HashMap<String, i64>,
+    days: &[NaiveDate],
+    total: i64,
+) -> Report {
+    let rows = days
+        .iter()
+        .map(|day| {
+            let name = day.format("%Y-%m-%d").to_string();
+            let seconds = *time_per_day.get(&name).unwrap_or(&0);
+            ReportRow {
+                name,
+                seconds,
+                duration: seconds_to_pretty(seconds),
+                percent: percent_of(seconds, total),
+            }
+        })
+        .collect();
+    Report {
+        rows,
+        total_seconds: total,
+        total_duration: seconds_to_pretty(total),
+    }
+}
+
+pub fn days_in_range(since: &str, until: &str) -> Vec<NaiveDate> {
+    let (Ok(since), Ok(until)) = (
+        NaiveDate::parse_from_str(since, "%Y-%m-%d"),
+        NaiveDate::parse_from_str(until, "%Y-%m-%d"),
+    ) else {
+        return Vec::new();
+    };
+    since.iter_days().take_while(|d| *d <= until).collect()
+}
+
+// Rows are sorted by time worked, largest first
+pub fn build_report(time_worked: HashMap<String, i64>, top: usize, total: i64) -> Report {
+    let mut rows: Vec<ReportRow> = group_top_x(time_worked, top)
+        .into_iter()
+        .map(|(name, seconds)| ReportRow {
+            name,
+            seconds,
+            duration: seconds_to_pretty(seconds),
+            percent: percent_of(seconds, total),
+        })
+        .collect();
+    rows.sort_by(|a, b| b.seconds.cmp(&a.seconds).then(a.name.cmp(&b.name)));
+    Report {
+        rows,
+        total_seconds: total,
+        total_duration: seconds_to_pretty(total),
+    }
+}
+
+pub fn report_to_delimited(report: &Report, delimiter: char) -> String {
+    let mut out = ["name", "seconds", "duration", "percent"].join(&delimiter.to_string()) + "\n";
+    let rows = report
+        .rows
+        .iter()
+        .map(|r| (r.name.as_str(), r.seconds, r.duration.as_str(), r.percent));
+    let total = std::iter::once((
+        "Total",
+        report.total_seconds,
+        report.total_duration.as_str(),
+        100.0,
+    ));
+    for (name, seconds, duration, percent) in rows.chain(total) {
+        out += &format!(
+            "{}{d}{}{d}{}{d}{:.2}\n",
+            escape_field(name, delimiter),
+            seconds,
+            duration,
+            percent,
+            d = delimiter
+        );
+    }
+    out
+}
+
+fn escape_field(field: &str, delimiter: char) -> String {
+    if delimiter == '\t' {
+        return field.replace(['\t', '\n'], " ");
+    }
+    if field.contains([delimiter, '"', '\n']) {
+        return format!("\"{}\"", field.replace('"', "\"\""));
+    }
+    field.to_string()
+}
+
+fn percent_of(seconds: i64, total: i64) -> f64 {
+    if total == 0 {
+        return 0.0;
+    }
+    (seconds as f64 / total as f64 * 10000.0).round() / 100.0
+}
+
 fn seconds_to_pretty(seconds: i64) -> String {
     let hours = seconds / 3600;
     let minutes = (seconds % 3600) / 60;
@@ -240,7 +748,7 @@ fn seconds_to_pretty(seconds: i64) -> String {
 
 fn group_top_x(hash_map: HashMap<String, i64>, x: usize) -> HashMap<String, i64> {
     let mut sorted_hash_map = hash_map.into_iter().collect::<Vec<(String, i64)>>();
-    sorted_hash_map.sort_by(|a, b| b.1.cmp(&a.1));
+    sorted_hash_map.sort_by_key(|a| std::cmp::Reverse(a.1));
     let other_total = sorted_hash_map.iter().skip(x).map(|x| x.1).sum();
     let mut ret_map: HashMap<String, i64> = sorted_hash_map.into_iter().take(x).collect();
     if other_total > 0 {
@@ -0,0 +1,118 @@
+use crate::cli::config::{get_profile_path, make_dir_if_none};
+use crate::elasticnow::backend::TimeWorkedBackend;
+use crate::elasticnow::servicenow_structs::Choice;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+// Cached categories are fetched again from sys_choice after a day
+const CACHE_MAX_AGE_SECS: i64 = 24 * 60 * 60;
+
+/// u_category choices fetched from sys_choice
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct CategoryCache {
+    /// Unix timestamp of when the choices were fetched
+    pub fetched_at: i64,
+    #[serde(default)]
+    pub categories: Vec<Choice>,
+}
+
+impl CategoryCache {
+    pub fn from_toml_file(path: &Path) -> io::Result<Self> {
+        let mut file = File::open(path)?;
+        let mut contents = String::new();
+        file.read_to_string(&mut contents)?;
+        toml::from_str(&contents).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn to_toml_file(&self, path: &Path) -> io::Result<()> {
+        make_dir_if_none();
+        let toml_string =
+            toml::to_string(self).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let mut file = File::create(path)?;
+        file.write_all(toml_string.as_bytes())
+    }
+
+    pub fn is_stale(&self) -> bool {
+        Utc::now().timestamp() - self.fetched_at > CACHE_MAX_AGE_SECS
+    }
+}
+
+// Categories are kept per profile since instances can have different choices
+pub fn get_categories_path(profile: &str) -> PathBuf {
+    get_profile_path(profile, "categories", "toml")
+}
+
+// Used when sys_choice cannot be read and nothing is cached
+pub fn default_categories() -> Vec<Choice> {
+    let choice = |value: &str, label: &str, hint: &str| Choice {
+        value: value.to_string(),
+        label: label.to_string(),
+        hint: hint.to_string(),
+    };
+    vec![
+        choice(
+            "certs_prodev_training",
+            "Training",
+            "Certs, Pro Dev, Training: Conferences, Studying or Taking Certifications, Webinars, On-boarding, or Employee to Employee Training",
+        ),
+        choice(
+            "univ_events",
+            "University Events",
+            "University Events: Convocation, You Matter",
+        ),
+        choice(
+            "clerical",
+            "Clerical",
+            "Clerical: Email, Operational Meetings, & Paperwork that cannot be tied to a task",
+        ),
+    ]
+}
+
+// Categories from config.toml when it sets any, otherwise from the cache or sys_choice. A stale
+// cache or the defaults are used when sys_choice cannot be read
+pub async fn load_categories<B: TimeWorkedBackend>(
+    backend: &B,
+    overrides: &[Choice],
+    cache_path: &Path,
+) -> Vec<Choice> {
+    if !overrides.is_empty() {
+        return overrides.to_vec();
+    }
+    let cache = CategoryCache::from_toml_file(cache_path).ok();
+    if let Some(cache) = &cache {
+        if !cache.is_stale() && !cache.categories.is_empty() {
+            return cache.categories.clone();
+        }
+    }
+    match backend.get_time_worked_categories().await {
+        Ok(categories) if !categories.is_empty() => {
+            let fetched = CategoryCache {
+                fetched_at: Utc::now().timestamp(),
+                categories,
+            };
+            if let Err(e) = fetched.to_toml_file(cache_path) {
+                tracing::warn!("Unable to cache categories in {:?}: {}", cache_path, e);
+            }
+            fetched.categories
+        }
+        result => {
+            if let Err(e) = result {
+                tracing::warn!("Unable to get categories from ServiceNow: {}", e);
+            }
+            match cache {
+                Some(cache) if !cache.categories.is_empty() => cache.categories,
+                _ => default_categories(),
+            }
+        }
+    }
+}
+
+// Finds a category by value or label, ignoring case
+pub fn find_category<'a>(categories: &'a [Choice], name: &str) -> Option<&'a Choice> {
+    categories
+        .iter()
+        .find(|c| c.value.eq_ignore_ascii_case(name) || c.label.eq_ignore_ascii_case(name))
+}
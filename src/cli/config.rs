@@ -1,42 +1,390 @@
+use crate::cli::credentials::{self, CredentialStore, Secrets};
+use crate::elasticnow::retry::RetryPolicy;
+use crate::elasticnow::servicenow_structs::Choice;
+use clap::ValueEnum;
 use dirs::config_dir;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::fs::File;
-use std::io::{self, Read, Write};
+use std::io::{self, Read};
 use std::path::PathBuf;
+use std::sync::OnceLock;
 
-#[derive(Serialize, Deserialize, Debug, Default)]
+// Fields added after the first release need a serde default so older files still load
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct Config {
+    /// Name of the profile in config.toml this was read from or is written to
+    #[serde(skip)]
+    pub profile: String,
+    /// ElasticNow session id, only written here with the config and command stores
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub id: String,
     pub instance: String,
+    /// Instance name (liberty) or base url (https://sn.example.edu:8443/servicenow)
     pub sn_instance: String,
     pub sn_username: String,
+    /// Only written here with the config store
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub sn_password: String,
     pub bin: String,
+    #[serde(default)]
+    pub credential_store: CredentialStore,
+    /// Prints the ServiceNow password, used with the command store (e.g. pass show sn)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password_command: Option<String>,
+    /// Passphrase secrets.enc was unlocked with, kept so it can be rewritten
+    #[serde(skip)]
+    pub passphrase: Option<String>,
+    /// Hours per day below which `report --daily` highlights a weekday
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub daily_target: Option<f64>,
+    /// How requests to ServiceNow are authenticated
+    #[serde(default)]
+    pub auth: AuthMethod,
+    /// Retries for requests to ServiceNow and ElasticNow
+    #[serde(default)]
+    pub retry: RetryPolicy,
+    /// OAuth application used with `auth = "oauth"`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub oauth: Option<OAuthConfig>,
+    /// Categories for time without a ticket, used instead of the choices from ServiceNow when set
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub categories: Vec<Choice>,
 }
 
-impl Config {
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum AuthMethod {
+    /// HTTP basic auth with the username and password on every request
+    #[default]
+    Basic,
+    /// OAuth tokens from /oauth_token.do, requested with the username and password
+    Oauth,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct OAuthConfig {
+    pub client_id: String,
+    /// Only written here with the config and command stores
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub client_secret: String,
+}
+
+pub const DEFAULT_PROFILE: &str = "default";
+// Layout of config.toml written by this version, 1 is a single config at the top level
+// from before profiles
+pub const CONFIG_VERSION: u32 = 2;
+
+// Profile requested with --profile or ELASTICNOW_PROFILE, resolved from config.toml otherwise
+static SELECTED_PROFILE: OnceLock<String> = OnceLock::new();
+
+/// Every profile in config.toml
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigFile {
+    /// CONFIG_VERSION when written, older files are migrated when read
+    #[serde(default = "current_version")]
+    pub version: u32,
+    /// Profile used when --profile and ELASTICNOW_PROFILE are not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_profile: Option<String>,
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Config>,
+}
+
+fn current_version() -> u32 {
+    CONFIG_VERSION
+}
+
+impl Default for ConfigFile {
+    fn default() -> Self {
+        Self {
+            version: CONFIG_VERSION,
+            default_profile: None,
+            profiles: BTreeMap::new(),
+        }
+    }
+}
+
+impl ConfigFile {
+    // Older files are rewritten in the current layout, keeping the original as config.toml.bak
     pub fn from_toml_file() -> io::Result<Self> {
-        let path = get_config_dir().join("config.toml");
-        let mut file = File::open(path)?;
+        let path = get_config_path();
+        let mut file = File::open(&path)?;
         let mut contents = String::new();
         file.read_to_string(&mut contents)?;
-        let config: Config = toml::from_str(&contents).unwrap_or_default();
-        Ok(config)
+        let (config_file, version) = Self::from_toml_str(&contents).map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!("invalid config file {}: {}", path.display(), e),
+            )
+        })?;
+        if version < CONFIG_VERSION {
+            let backup = path.with_extension("toml.bak");
+            credentials::write_private(&backup, contents.as_bytes())?;
+            config_file.to_toml_file()?;
+            tracing::info!(
+                "Migrated {} from version {} to {}, the original is in {}",
+                path.display(),
+                version,
+                CONFIG_VERSION,
+                backup.display()
+            );
+        }
+        Ok(config_file)
+    }
+
+    // Parses config.toml in any known version, returning the version it was in. Unknown keys and
+    // invalid values are errors with the line and column they are on
+    pub fn from_toml_str(contents: &str) -> io::Result<(Self, u32)> {
+        let invalid =
+            |e: toml::de::Error| io::Error::new(io::ErrorKind::InvalidData, e.to_string());
+        let table: toml::Table = toml::from_str(contents).map_err(invalid)?;
+        let version = match table.get("version") {
+            Some(version) => version
+                .as_integer()
+                .and_then(|v| u32::try_from(v).ok())
+                .ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "version must be a positive integer",
+                    )
+                })?,
+            // Files written before versioning with profiles are the first version 2 files
+            None if table.contains_key("profiles") => 2,
+            None => 1,
+        };
+        match version {
+            // A single config at the top level becomes the default profile
+            1 => {
+                let config: Config = toml::from_str(contents).map_err(invalid)?;
+                Ok((
+                    Self {
+                        profiles: BTreeMap::from([(DEFAULT_PROFILE.to_string(), config)]),
+                        ..Default::default()
+                    },
+                    version,
+                ))
+            }
+            CONFIG_VERSION => {
+                let config_file: Self = toml::from_str(contents).map_err(invalid)?;
+                for name in config_file
+                    .profiles
+                    .keys()
+                    .chain(config_file.default_profile.as_ref())
+                {
+                    parse_profile_name(name)
+                        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                }
+                Ok((
+                    Self {
+                        version: CONFIG_VERSION,
+                        ..config_file
+                    },
+                    version,
+                ))
+            }
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "version {} is newer than this elasticnow supports ({}), please upgrade",
+                    version, CONFIG_VERSION
+                ),
+            )),
+        }
     }
 
     pub fn to_toml_file(&self) -> io::Result<()> {
-        let path = get_config_dir().join("config.toml");
-        let toml_string = toml::to_string(self).unwrap_or_default();
-        let mut file = File::create(path)?;
-        file.write_all(toml_string.as_bytes())?;
+        let toml_string = toml::to_string(self).map_err(io::Error::other)?;
+        credentials::write_private(&get_config_path(), toml_string.as_bytes())
+    }
+
+    // The default_profile, the only profile, or the profile named default
+    pub fn resolve_profile(&self) -> String {
+        if let Some(default_profile) = &self.default_profile {
+            return default_profile.clone();
+        }
+        match self.profiles.keys().collect::<Vec<_>>()[..] {
+            [only] => only.clone(),
+            _ => DEFAULT_PROFILE.to_string(),
+        }
+    }
+
+    pub fn get_profile(&self, name: &str) -> io::Result<Config> {
+        let mut config = self.profiles.get(name).cloned().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!(
+                    "profile {} is not in config.toml (profiles: {})",
+                    name,
+                    self.profiles.keys().cloned().collect::<Vec<_>>().join(", ")
+                ),
+            )
+        })?;
+        config.profile = name.to_string();
+        Ok(config)
+    }
+}
+
+// Profile names end up in file names, so only letters, digits, - and _ are allowed
+pub fn parse_profile_name(name: &str) -> Result<String, String> {
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(format!(
+            "invalid profile name \"{}\", only letters, digits, - and _ are allowed",
+            name
+        ));
+    }
+    Ok(name.to_string())
+}
+
+// Sets the profile for this run, only the first call has an effect
+pub fn select_profile(name: Option<String>) {
+    if let Some(name) = name {
+        let _ = SELECTED_PROFILE.set(name);
+    }
+}
+
+pub fn get_profile_name() -> String {
+    SELECTED_PROFILE
+        .get_or_init(|| {
+            ConfigFile::from_toml_file()
+                .map(|file| file.resolve_profile())
+                .unwrap_or(DEFAULT_PROFILE.to_string())
+        })
+        .clone()
+}
+
+// File in the config dir kept per profile, e.g. queue.toml for the default profile and
+// queue.dev.toml for the dev profile
+pub fn get_profile_path(profile: &str, stem: &str, extension: &str) -> PathBuf {
+    if profile == DEFAULT_PROFILE {
+        get_config_dir().join(format!("{}.{}", stem, extension))
+    } else {
+        get_config_dir().join(format!("{}.{}.{}", stem, profile, extension))
+    }
+}
+
+impl Config {
+    // Reads the selected profile
+    pub fn from_toml_file() -> io::Result<Self> {
+        ConfigFile::from_toml_file()?.get_profile(&get_profile_name())
+    }
+
+    // Fills in id and sn_password from the credential store
+    pub fn load_credentials(&mut self) -> io::Result<()> {
+        if self.profile.is_empty() {
+            self.profile = get_profile_name();
+        }
+        match self.credential_store {
+            CredentialStore::Config => {}
+            CredentialStore::Command => {
+                let command = self.password_command.as_deref().ok_or_else(|| {
+                    io::Error::other("password_command must be set to use the command store")
+                })?;
+                self.sn_password = credentials::run_password_command(command)?;
+            }
+            CredentialStore::Encrypted => {
+                let data = std::fs::read(credentials::get_secrets_path(&self.profile))?;
+                let passphrase = credentials::get_passphrase(false)?;
+                let secrets = credentials::decrypt_secrets(&data, &passphrase)?;
+                self.id = secrets.id;
+                self.sn_password = secrets.sn_password;
+                if let Some(oauth) = &mut self.oauth {
+                    oauth.client_secret = secrets.oauth_client_secret;
+                }
+                self.passphrase = Some(passphrase);
+            }
+            CredentialStore::Env => {
+                self.sn_password = std::env::var("SN_PASSWORD").map_err(|_| {
+                    io::Error::other("SN_PASSWORD must be set to use the env store")
+                })?;
+                if let Ok(id) = std::env::var("ELASTICNOW_ID") {
+                    self.id = id;
+                }
+                if let (Some(oauth), Ok(secret)) =
+                    (&mut self.oauth, std::env::var("SN_OAUTH_CLIENT_SECRET"))
+                {
+                    oauth.client_secret = secret;
+                }
+            }
+        }
         Ok(())
     }
+
+    // Writes this profile to config.toml, and secrets.enc for the encrypted store, readable
+    // only by the owner. Other profiles are kept as they are
+    pub fn to_toml_file(&mut self) -> io::Result<()> {
+        if self.profile.is_empty() {
+            self.profile = get_profile_name();
+        }
+        let mut stored = Config {
+            passphrase: None,
+            ..self.clone()
+        };
+        match self.credential_store {
+            CredentialStore::Config => {}
+            CredentialStore::Command => stored.sn_password.clear(),
+            CredentialStore::Encrypted => {
+                let passphrase = match &self.passphrase {
+                    Some(passphrase) => passphrase.clone(),
+                    None => credentials::get_passphrase(true)?,
+                };
+                let secrets = Secrets {
+                    id: self.id.clone(),
+                    sn_password: self.sn_password.clone(),
+                    oauth_client_secret: self
+                        .oauth
+                        .as_ref()
+                        .map(|o| o.client_secret.clone())
+                        .unwrap_or_default(),
+                };
+                credentials::write_private(
+                    &credentials::get_secrets_path(&self.profile),
+                    &credentials::encrypt_secrets(&secrets, &passphrase)?,
+                )?;
+                self.passphrase = Some(passphrase);
+                stored.clear_secrets();
+            }
+            CredentialStore::Env => stored.clear_secrets(),
+        }
+        let mut file = match ConfigFile::from_toml_file() {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => ConfigFile::default(),
+            Err(e) => return Err(e),
+        };
+        // The first profile set up stays the default when more are added
+        if file.profiles.is_empty() && self.profile != DEFAULT_PROFILE {
+            file.default_profile = Some(self.profile.clone());
+        }
+        file.profiles.insert(self.profile.clone(), stored);
+        file.to_toml_file()
+    }
+    fn clear_secrets(&mut self) {
+        self.id.clear();
+        self.sn_password.clear();
+        if let Some(oauth) = &mut self.oauth {
+            oauth.client_secret.clear();
+        }
+    }
     pub fn set_new_id(&mut self, _id: &str) -> bool {
         self.id = _id.to_string();
+        if self.credential_store == CredentialStore::Env {
+            tracing::warn!("Export ELASTICNOW_ID to keep the new ElasticNow session");
+        }
         self.to_toml_file().is_ok()
     }
 }
 
+pub fn get_config_path() -> PathBuf {
+    get_config_dir().join("config.toml")
+}
+
 pub fn get_config_dir() -> PathBuf {
     let config_dir = config_dir().expect("Unable to get config directory");
     PathBuf::from(format!("{}/elasticnow", config_dir.display()))
@@ -44,9 +392,142 @@ pub fn get_config_dir() -> PathBuf {
 
 pub fn make_dir_if_none() {
     let config_dir = get_config_dir();
-    if !config_dir.exists() {
-        if std::fs::create_dir_all(&config_dir).is_err() {
-            tracing::error!("Unable to create config directory {:?}", config_dir);
+    if !config_dir.exists() && std::fs::create_dir_all(&config_dir).is_err() {
+        tracing::error!("Unable to create config directory {:?}", config_dir);
+    }
+}
+
+// Keys accepted by `config get` and `config set`, nested tables use dots
+pub const CONFIG_KEYS: &[&str] = &[
+    "id",
+    "instance",
+    "sn_instance",
+    "sn_username",
+    "sn_password",
+    "bin",
+    "credential_store",
+    "password_command",
+    "daily_target",
+    "auth",
+    "retry.max_attempts",
+    "retry.base_delay_ms",
+    "retry.timeout_secs",
+    "oauth.client_id",
+    "oauth.client_secret",
+];
+// Keys config set refuses, values on the command line end up in the shell history
+const SECRET_KEYS: &[&str] = &["id", "sn_password", "oauth.client_secret"];
+const MASK: &str = "********";
+
+fn invalid_key(key: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!(
+            "unknown config key {} (keys: {})",
+            key,
+            CONFIG_KEYS.join(", ")
+        ),
+    )
+}
+
+impl Config {
+    fn to_table(&self) -> io::Result<toml::Table> {
+        toml::Table::try_from(self).map_err(io::Error::other)
+    }
+
+    // Value of a key such as bin or retry.max_attempts, None when it is not set
+    pub fn get_value(&self, key: &str) -> io::Result<Option<toml::Value>> {
+        if !CONFIG_KEYS.contains(&key) {
+            return Err(invalid_key(key));
+        }
+        let table = self.to_table()?;
+        let mut value: Option<&toml::Value> = None;
+        for part in key.split('.') {
+            value = match value {
+                None => table.get(part),
+                Some(parent) => parent.get(part),
+            };
+            if value.is_none() {
+                return Ok(None);
+            }
+        }
+        Ok(value.cloned())
+    }
+
+    // Sets a key from the command line, values are read as TOML (6.5, true) and otherwise as
+    // strings, so bin=Linux and bin="Linux" are the same
+    pub fn set_value(&mut self, key: &str, raw: &str) -> io::Result<()> {
+        if !CONFIG_KEYS.contains(&key) {
+            return Err(invalid_key(key));
+        }
+        if SECRET_KEYS.contains(&key) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "{} is a secret and cannot be set here, run elasticnow setup to be prompted for it",
+                    key
+                ),
+            ));
+        }
+        let parsed = toml::from_str::<toml::Table>(&format!("value = {}", raw))
+            .ok()
+            .and_then(|mut t| t.remove("value"));
+        let candidates = parsed
+            .into_iter()
+            .chain([toml::Value::String(raw.to_string())]);
+        let mut last_error = None;
+        for value in candidates {
+            let mut table = self.to_table()?;
+            let mut parent = &mut table;
+            let mut parts: Vec<&str> = key.split('.').collect();
+            let last = parts.pop().unwrap_or_default();
+            for part in parts {
+                parent = parent
+                    .entry(part)
+                    .or_insert_with(|| toml::Value::Table(toml::Table::new()))
+                    .as_table_mut()
+                    .ok_or_else(|| invalid_key(key))?;
+            }
+            parent.insert(last.to_string(), value);
+            match table.try_into::<Config>() {
+                Ok(config) => {
+                    *self = Config {
+                        profile: self.profile.clone(),
+                        passphrase: self.passphrase.clone(),
+                        ..config
+                    };
+                    return Ok(());
+                }
+                Err(e) => last_error = Some(e),
+            }
+        }
+        Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "invalid value {} for {}: {}",
+                raw,
+                key,
+                last_error.map(|e| e.to_string()).unwrap_or_default()
+            ),
+        ))
+    }
+
+    // Copy with the secrets that are set replaced, for printing
+    pub fn masked(&self) -> Config {
+        let mask = |secret: &str| {
+            if secret.is_empty() {
+                String::new()
+            } else {
+                MASK.to_string()
+            }
+        };
+        let mut masked = self.clone();
+        masked.id = mask(&self.id);
+        masked.sn_password = mask(&self.sn_password);
+        if let Some(oauth) = &mut masked.oauth {
+            oauth.client_secret = mask(&oauth.client_secret);
         }
+        masked.passphrase = None;
+        masked
     }
 }
@@ -0,0 +1,49 @@
+use crate::elasticnow::backend::{ChangeBackend, TicketBackend};
+use crate::elasticnow::error::{Error, Result};
+use crate::elasticnow::servicenow_structs::SysIdResult;
+
+// Returns the sys_id of the created ticket
+pub async fn create_ticket<B: TicketBackend>(
+    backend: &B,
+    bin: &str,
+    description: &str,
+) -> Result<String> {
+    let description = description.trim();
+    if description.is_empty() {
+        return Err(Error::Validation("Short description is empty".to_string()));
+    }
+    tracing::debug!("Creating new ticket: {}", description);
+    backend.create_ticket(bin, description).await
+}
+
+// Creates a standard change for bin from template_id, or from the template choose picks out of the
+// templates matching search. Returns the sys_id of the created CHG
+pub async fn create_std_chg<B, F>(
+    backend: &B,
+    search: &str,
+    bin: &str,
+    template_id: Option<String>,
+    choose: F,
+) -> Result<String>
+where
+    B: ChangeBackend,
+    F: FnOnce(Vec<SysIdResult>) -> String,
+{
+    let template_sys_id = match template_id {
+        Some(template_id) => template_id,
+        None => {
+            let templates = backend.search_std_chg(search).await?;
+            if templates.is_empty() {
+                return Err(Error::NotFound {
+                    service: "ServiceNow",
+                    what: format!("std chg templates matching \"{}\"", search),
+                });
+            }
+            choose(templates)
+        }
+    };
+    tracing::debug!("Selected chg_id: {}", template_sys_id);
+    backend
+        .create_std_chg_from_template(&template_sys_id, bin)
+        .await
+}
@@ -0,0 +1,142 @@
+use crate::cli::config::get_profile_path;
+pub use crate::elasticnow::files::write_private;
+use clap::ValueEnum;
+use openssl::hash::MessageDigest;
+use openssl::pkcs5::pbkdf2_hmac;
+use openssl::rand::rand_bytes;
+use openssl::symm::{decrypt_aead, encrypt_aead, Cipher};
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::path::PathBuf;
+
+/// Where the ServiceNow password and the ElasticNow session id are kept
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum CredentialStore {
+    /// In config.toml, readable only by the owner
+    #[default]
+    Config,
+    /// Password printed by `password_command`, the session id stays in config.toml
+    Command,
+    /// In secrets.enc, encrypted with a passphrase
+    Encrypted,
+    /// Only read from SN_PASSWORD, ELASTICNOW_ID and SN_OAUTH_CLIENT_SECRET, nothing is written to disk
+    Env,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct Secrets {
+    pub id: String,
+    pub sn_password: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub oauth_client_secret: String,
+}
+
+// Passphrase for secrets.enc, prompted for when unset
+pub const PASSPHRASE_ENV: &str = "ELASTICNOW_PASSPHRASE";
+
+// secrets.enc is MAGIC, salt, nonce, AES-256-GCM tag and then the encrypted TOML
+const MAGIC: &[u8] = b"ENOW1";
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+const PBKDF2_ITERATIONS: usize = 200_000;
+
+pub fn get_secrets_path(profile: &str) -> PathBuf {
+    get_profile_path(profile, "secrets", "enc")
+}
+
+fn derive_key(passphrase: &str, salt: &[u8]) -> io::Result<[u8; 32]> {
+    let mut key = [0u8; 32];
+    pbkdf2_hmac(
+        passphrase.as_bytes(),
+        salt,
+        PBKDF2_ITERATIONS,
+        MessageDigest::sha256(),
+        &mut key,
+    )
+    .map_err(io::Error::other)?;
+    Ok(key)
+}
+
+pub fn encrypt_secrets(secrets: &Secrets, passphrase: &str) -> io::Result<Vec<u8>> {
+    let plaintext = toml::to_string(secrets).map_err(io::Error::other)?;
+    let mut salt = [0u8; SALT_LEN];
+    let mut nonce = [0u8; NONCE_LEN];
+    rand_bytes(&mut salt).map_err(io::Error::other)?;
+    rand_bytes(&mut nonce).map_err(io::Error::other)?;
+    let key = derive_key(passphrase, &salt)?;
+    let mut tag = [0u8; TAG_LEN];
+    let ciphertext = encrypt_aead(
+        Cipher::aes_256_gcm(),
+        &key,
+        Some(&nonce),
+        MAGIC,
+        plaintext.as_bytes(),
+        &mut tag,
+    )
+    .map_err(io::Error::other)?;
+    Ok([MAGIC, &salt, &nonce, &tag, &ciphertext].concat())
+}
+
+pub fn decrypt_secrets(data: &[u8], passphrase: &str) -> io::Result<Secrets> {
+    let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+    let data = data
+        .strip_prefix(MAGIC)
+        .filter(|d| d.len() >= SALT_LEN + NONCE_LEN + TAG_LEN)
+        .ok_or_else(|| invalid("secrets file is not in a known format"))?;
+    let (salt, data) = data.split_at(SALT_LEN);
+    let (nonce, data) = data.split_at(NONCE_LEN);
+    let (tag, ciphertext) = data.split_at(TAG_LEN);
+    let key = derive_key(passphrase, salt)?;
+    let plaintext = decrypt_aead(
+        Cipher::aes_256_gcm(),
+        &key,
+        Some(nonce),
+        MAGIC,
+        ciphertext,
+        tag,
+    )
+    .map_err(|_| invalid("unable to decrypt secrets, is the passphrase correct?"))?;
+    let plaintext = String::from_utf8(plaintext).map_err(|_| invalid("secrets are not UTF-8"))?;
+    toml::from_str(&plaintext).map_err(|e| invalid(&e.to_string()))
+}
+
+// Reads the passphrase from ELASTICNOW_PASSPHRASE or prompts for it
+pub fn get_passphrase(confirm: bool) -> io::Result<String> {
+    if let Ok(passphrase) = std::env::var(PASSPHRASE_ENV) {
+        return Ok(passphrase);
+    }
+    let theme = dialoguer::theme::ColorfulTheme::default();
+    let mut prompt = dialoguer::Password::with_theme(&theme).with_prompt("Secrets passphrase");
+    if confirm {
+        prompt = prompt.with_confirmation("Confirm passphrase", "Passphrases do not match");
+    }
+    prompt.interact().map_err(io::Error::other)
+}
+
+// Runs password_command through the shell and returns the first line it prints
+pub fn run_password_command(command: &str) -> io::Result<String> {
+    #[cfg(windows)]
+    let output = std::process::Command::new("cmd")
+        .args(["/C", command])
+        .output()?;
+    #[cfg(not(windows))]
+    let output = std::process::Command::new("sh")
+        .args(["-c", command])
+        .output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "password_command exited with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    match stdout.lines().next().map(str::trim_end) {
+        Some(password) if !password.is_empty() => Ok(password.to_string()),
+        _ => Err(io::Error::other(
+            "password_command did not print a password",
+        )),
+    }
+}
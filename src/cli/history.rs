@@ -0,0 +1,188 @@
+use crate::cli::config::{get_profile_name, get_profile_path, make_dir_if_none};
+use crate::elasticnow::files::write_atomic;
+use chrono::{Local, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::PathBuf;
+
+// Recent tickets shown in the picker, after the favourites
+pub const RECENT_LIMIT: usize = 5;
+// Tickets kept in the history besides favourites, the least recently used are dropped
+const HISTORY_LIMIT: usize = 50;
+
+/// A ticket time was tracked on or that was pinned with `fav add`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct HistoryEntry {
+    pub sys_id: String,
+    pub number: String,
+    pub short_description: String,
+    /// Unix timestamp of when time was last tracked on the ticket, 0 when it never was
+    #[serde(default)]
+    pub last_used: i64,
+    /// Times time was tracked on the ticket
+    #[serde(default)]
+    pub count: u32,
+    #[serde(default)]
+    pub favourite: bool,
+}
+
+impl HistoryEntry {
+    pub fn label(&self) -> String {
+        let mark = if self.favourite { "★" } else { "↺" };
+        format!("{} {}: {}", mark, self.number, self.short_description)
+    }
+
+    pub fn last_used_string(&self) -> String {
+        Local
+            .timestamp_opt(self.last_used, 0)
+            .single()
+            .map(|t| t.format("%Y-%m-%d %H:%M").to_string())
+            .unwrap_or_default()
+    }
+
+    pub fn usage(&self) -> String {
+        match self.count {
+            0 => "Never used".to_string(),
+            1 => format!("Used once, on {}", self.last_used_string()),
+            count => format!("Used {} times, last on {}", count, self.last_used_string()),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct History {
+    #[serde(default)]
+    pub tickets: Vec<HistoryEntry>,
+}
+
+impl History {
+    pub fn from_toml_file() -> io::Result<Self> {
+        let path = get_history_path();
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let mut file = File::open(path)?;
+        let mut contents = String::new();
+        file.read_to_string(&mut contents)?;
+        toml::from_str(&contents).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn to_toml_file(&self) -> io::Result<()> {
+        make_dir_if_none();
+        let toml_string =
+            toml::to_string(self).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        write_atomic(&get_history_path(), toml_string.as_bytes())
+    }
+
+    fn find_mut(&mut self, sys_id: &str) -> Option<&mut HistoryEntry> {
+        self.tickets.iter_mut().find(|t| t.sys_id == sys_id)
+    }
+
+    // Counts time tracked on a ticket, keeping the number and description up to date
+    pub fn record(&mut self, sys_id: &str, number: &str, short_description: &str) {
+        let now = Utc::now().timestamp();
+        match self.find_mut(sys_id) {
+            Some(entry) => {
+                entry.last_used = now;
+                entry.count += 1;
+                entry.number = number.to_string();
+                entry.short_description = short_description.to_string();
+            }
+            None => self.tickets.push(HistoryEntry {
+                sys_id: sys_id.to_string(),
+                number: number.to_string(),
+                short_description: short_description.to_string(),
+                last_used: now,
+                count: 1,
+                favourite: false,
+            }),
+        }
+        self.prune();
+    }
+
+    // Drops the least recently used tickets over HISTORY_LIMIT, favourites are always kept
+    fn prune(&mut self) {
+        let mut recent: Vec<(i64, String)> = self
+            .tickets
+            .iter()
+            .filter(|t| !t.favourite)
+            .map(|t| (t.last_used, t.sys_id.clone()))
+            .collect();
+        if recent.len() <= HISTORY_LIMIT {
+            return;
+        }
+        recent.sort_by_key(|t| Reverse(t.0));
+        let dropped: Vec<String> = recent
+            .split_off(HISTORY_LIMIT)
+            .into_iter()
+            .map(|(_, sys_id)| sys_id)
+            .collect();
+        self.tickets
+            .retain(|t| t.favourite || !dropped.contains(&t.sys_id));
+    }
+
+    pub fn add_favourite(&mut self, sys_id: &str, number: &str, short_description: &str) {
+        match self.find_mut(sys_id) {
+            Some(entry) => {
+                entry.favourite = true;
+                entry.number = number.to_string();
+                entry.short_description = short_description.to_string();
+            }
+            None => self.tickets.push(HistoryEntry {
+                sys_id: sys_id.to_string(),
+                number: number.to_string(),
+                short_description: short_description.to_string(),
+                favourite: true,
+                ..Default::default()
+            }),
+        }
+    }
+
+    // Unpins a favourite by number or sys_id, returning false when it was not one. Tickets time
+    // was never tracked on are forgotten
+    pub fn remove_favourite(&mut self, ticket: &str) -> bool {
+        let Some(entry) = self
+            .tickets
+            .iter_mut()
+            .find(|t| t.favourite && (t.number.eq_ignore_ascii_case(ticket) || t.sys_id == ticket))
+        else {
+            return false;
+        };
+        entry.favourite = false;
+        self.tickets.retain(|t| t.favourite || t.count > 0);
+        true
+    }
+
+    pub fn favourites(&self) -> Vec<&HistoryEntry> {
+        let mut favourites: Vec<&HistoryEntry> =
+            self.tickets.iter().filter(|t| t.favourite).collect();
+        favourites.sort_by(|a, b| a.number.cmp(&b.number));
+        favourites
+    }
+
+    // Most recently used tickets that are not favourites
+    pub fn recent(&self, limit: usize) -> Vec<&HistoryEntry> {
+        let mut recent: Vec<&HistoryEntry> = self
+            .tickets
+            .iter()
+            .filter(|t| !t.favourite && t.count > 0)
+            .collect();
+        recent.sort_by_key(|t| Reverse(t.last_used));
+        recent.truncate(limit);
+        recent
+    }
+
+    // Favourites and then the recent tickets, in the order the picker shows them
+    pub fn picker_entries(&self) -> Vec<&HistoryEntry> {
+        let mut entries = self.favourites();
+        entries.extend(self.recent(RECENT_LIMIT));
+        entries
+    }
+}
+
+// History is kept per profile since sys_ids differ between instances
+pub fn get_history_path() -> PathBuf {
+    get_profile_path(&get_profile_name(), "history", "toml")
+}
@@ -0,0 +1,183 @@
+use crate::cli::timetrack::{resolve_target, TimeEntry, TimeTarget};
+use crate::elasticnow::backend::TicketBackend;
+use crate::elasticnow::error::{Error, Result};
+use crate::elasticnow::files::write_atomic;
+use crate::elasticnow::servicenow::parse_time_worked;
+use crate::elasticnow::servicenow_structs::Choice;
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// One row of a timesheet, from a CSV file with a header or a JSON array of objects
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Default)]
+pub struct ImportRow {
+    /// Day the time is tracked for (YYYY-MM-DD)
+    pub date: String,
+    /// Ticket number or no-ticket category
+    #[serde(alias = "ticket", alias = "category")]
+    pub target: String,
+    #[serde(alias = "time_worked")]
+    pub duration: String,
+    #[serde(default)]
+    pub comment: String,
+}
+
+/// A row that passed validation, ready to post
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValidRow {
+    pub entry: TimeEntry,
+    /// Ticket number and short description, or the category label
+    pub label: String,
+    pub seconds: i64,
+}
+
+/// Outcome of one row, written to the result file
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ImportResult {
+    /// 1-based position of the row in the file, not counting the CSV header
+    pub row: usize,
+    pub date: String,
+    pub target: String,
+    pub duration: String,
+    pub comment: String,
+    /// tracked, failed, unknown when a timeout or 5xx may have tracked it anyway, pending until
+    /// posted, invalid or skipped when other rows were invalid
+    pub status: String,
+    /// sys_id of the created task_time_worked record
+    pub sys_id: String,
+    pub error: String,
+}
+
+impl ImportResult {
+    pub fn new(row: usize, input: &ImportRow, status: &str) -> Self {
+        Self {
+            row,
+            date: input.date.clone(),
+            target: input.target.clone(),
+            duration: input.duration.clone(),
+            comment: input.comment.clone(),
+            status: status.to_string(),
+            sys_id: String::new(),
+            error: String::new(),
+        }
+    }
+}
+
+fn is_json(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("json"))
+}
+
+// Reads JSON when the extension is .json and CSV otherwise. CSV headers are matched ignoring case
+pub fn read_rows(path: &Path) -> io::Result<Vec<ImportRow>> {
+    let invalid =
+        |e: &dyn std::fmt::Display| io::Error::new(io::ErrorKind::InvalidData, e.to_string());
+    if is_json(path) {
+        let contents = std::fs::read_to_string(path)?;
+        return serde_json::from_str(&contents).map_err(|e| invalid(&e));
+    }
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_path(path)
+        .map_err(|e| invalid(&e))?;
+    let headers: csv::StringRecord = reader
+        .headers()
+        .map_err(|e| invalid(&e))?
+        .iter()
+        .map(str::to_lowercase)
+        .collect();
+    reader.set_headers(headers);
+    reader
+        .deserialize()
+        .collect::<std::result::Result<Vec<ImportRow>, csv::Error>>()
+        .map_err(|e| invalid(&e))
+}
+
+// Results go next to the input, e.g. timesheet.csv gives timesheet.results.csv
+pub fn get_results_path(path: &Path) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let ext = if is_json(path) { "json" } else { "csv" };
+    path.with_file_name(format!("{}.results.{}", stem, ext))
+}
+
+pub fn write_results(path: &Path, results: &[ImportResult]) -> io::Result<()> {
+    let contents = if is_json(path) {
+        let json = serde_json::to_string_pretty(results).map_err(io::Error::other)?;
+        (json + "\n").into_bytes()
+    } else {
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        for result in results {
+            writer.serialize(result).map_err(io::Error::other)?;
+        }
+        writer.into_inner().map_err(io::Error::other)?
+    };
+    write_atomic(path, &contents)
+}
+
+// Checks every row before anything is posted, resolving each target once. Errors only when ServiceNow cannot be asked,
+// invalid rows get their reason instead
+pub async fn validate_rows<B: TicketBackend>(
+    backend: &B,
+    categories: &[Choice],
+    rows: &[ImportRow],
+) -> Result<Vec<std::result::Result<ValidRow, String>>> {
+    let mut targets: HashMap<String, std::result::Result<(TimeTarget, String), String>> =
+        HashMap::new();
+    let mut validated = Vec::new();
+    for row in rows {
+        let date = match NaiveDate::parse_from_str(&row.date, "%Y-%m-%d") {
+            Ok(date) => date.format("%Y-%m-%d").to_string(),
+            Err(_) => {
+                validated.push(Err(format!(
+                    "Invalid date \"{}\", expected YYYY-MM-DD",
+                    row.date
+                )));
+                continue;
+            }
+        };
+        let seconds = match parse_time_worked(&row.duration) {
+            Ok(seconds) => seconds,
+            Err(e) => {
+                validated.push(Err(e.to_string()));
+                continue;
+            }
+        };
+        if row.comment.is_empty() {
+            validated.push(Err("Comment is empty".to_string()));
+            continue;
+        }
+        if row.target.is_empty() {
+            validated.push(Err("Ticket or category is empty".to_string()));
+            continue;
+        }
+        let key = row.target.to_uppercase();
+        if !targets.contains_key(&key) {
+            let resolved = match resolve_target(backend, categories, &row.target).await {
+                Ok(resolved) => Ok((resolved.target, resolved.label)),
+                Err(Error::Validation(e)) => Err(e),
+                Err(e) => return Err(e),
+            };
+            targets.insert(key.clone(), resolved);
+        }
+        let (target, label) = match &targets[&key] {
+            Ok((target, label)) => (target.clone(), label.clone()),
+            Err(e) => {
+                validated.push(Err(e.clone()));
+                continue;
+            }
+        };
+        validated.push(Ok(ValidRow {
+            entry: TimeEntry {
+                target,
+                time_worked: row.duration.clone(),
+                comment: row.comment.clone(),
+                date: Some(date),
+            },
+            label,
+            seconds,
+        }));
+    }
+    Ok(validated)
+}
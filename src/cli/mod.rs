@@ -1,5 +1,15 @@
 pub mod args;
+pub mod categories;
 pub mod config;
+pub mod create;
+pub mod credentials;
+pub mod history;
+pub mod import;
+pub mod picker;
+pub mod queue;
+pub mod report;
+pub mod timer;
+pub mod timetrack;
 
 #[cfg(test)]
 mod test {
@@ -8,4 +18,325 @@ mod test {
     fn test_time_validator() {
         assert_eq!(args::range_format_validate("2010-01-01").unwrap(), ());
     }
+
+    #[test]
+    fn test_timer_seconds_to_time_worked() {
+        assert_eq!(timer::seconds_to_time_worked(0), "1m");
+        assert_eq!(timer::seconds_to_time_worked(89), "1m");
+        assert_eq!(timer::seconds_to_time_worked(3600), "1h");
+        assert_eq!(timer::seconds_to_time_worked(5430), "1h31m");
+    }
+
+    #[test]
+    fn test_report_csv() {
+        let time_worked = std::collections::HashMap::from([
+            ("Clerical".to_string(), 3600),
+            ("IT, Networking".to_string(), 10800),
+        ]);
+        let report = args::build_report(time_worked, 10, 14400);
+        assert_eq!(
+            args::report_to_delimited(&report, ','),
+            "name,seconds,duration,percent\n\
+             \"IT, Networking\",10800,03:00:00,75.00\n\
+             Clerical,3600,01:00:00,25.00\n\
+             Total,14400,04:00:00,100.00\n"
+        );
+    }
+
+    #[test]
+    fn test_daily_report_includes_empty_days() {
+        let days = args::days_in_range("2024-06-29", "2024-07-01");
+        let time_per_day = std::collections::HashMap::from([("2024-06-30".to_string(), 1800)]);
+        let report = args::build_daily_report(&time_per_day, &days, 1800);
+        let rows: Vec<(&str, i64)> = report
+            .rows
+            .iter()
+            .map(|r| (r.name.as_str(), r.seconds))
+            .collect();
+        assert_eq!(
+            rows,
+            vec![("2024-06-29", 0), ("2024-06-30", 1800), ("2024-07-01", 0)]
+        );
+    }
+
+    #[test]
+    fn test_queue_toml_round_trip() {
+        let queue = queue::Queue {
+            entries: vec![queue::QueuedEntry {
+                sys_id: None,
+                category: Some("clerical".to_string()),
+                time_worked: "1h".to_string(),
+                comment: "Email".to_string(),
+                date: "2024-06-24".to_string(),
+                last_error: None,
+            }],
+        };
+        let parsed: queue::Queue = toml::from_str(&toml::to_string(&queue).unwrap()).unwrap();
+        assert_eq!(parsed.entries, queue.entries);
+    }
+
+    #[test]
+    fn test_encrypted_secrets_round_trip() {
+        use credentials::{decrypt_secrets, encrypt_secrets, Secrets};
+        let secrets = Secrets {
+            id: "session".to_string(),
+            sn_password: "hunter2".to_string(),
+            ..Default::default()
+        };
+        let data = encrypt_secrets(&secrets, "correct horse").unwrap();
+        assert!(!String::from_utf8_lossy(&data).contains("hunter2"));
+        assert_eq!(decrypt_secrets(&data, "correct horse").unwrap(), secrets);
+        assert!(decrypt_secrets(&data, "wrong").is_err());
+        assert!(decrypt_secrets(b"ENOW1", "correct horse").is_err());
+    }
+
+    #[test]
+    fn test_password_command() {
+        use credentials::run_password_command;
+        assert_eq!(
+            run_password_command("echo hunter2; echo ignored").unwrap(),
+            "hunter2"
+        );
+        assert!(run_password_command("exit 1").is_err());
+        assert!(run_password_command("true").is_err());
+    }
+
+    #[test]
+    fn test_config_profiles() {
+        use config::{ConfigFile, DEFAULT_PROFILE};
+        let (legacy, version) = ConfigFile::from_toml_str(
+            "id = \"x\"\ninstance = \"i\"\nsn_instance = \"liberty\"\nsn_username = \"u\"\nbin = \"Linux\"\n",
+        )
+        .unwrap();
+        assert_eq!(version, 1);
+        assert_eq!(legacy.version, config::CONFIG_VERSION);
+        assert_eq!(legacy.resolve_profile(), DEFAULT_PROFILE);
+        let config = legacy.get_profile(DEFAULT_PROFILE).unwrap();
+        assert_eq!(config.sn_instance, "liberty");
+        assert_eq!(config.profile, DEFAULT_PROFILE);
+
+        let file = ConfigFile::from_toml_str(
+            r#"
+[profiles.dev]
+instance = "i"
+sn_instance = "libertydev"
+sn_username = "u"
+bin = "Linux"
+
+[profiles.prod]
+instance = "i"
+sn_instance = "liberty"
+sn_username = "u"
+bin = "Linux"
+"#,
+        )
+        .unwrap()
+        .0;
+        assert_eq!(file.resolve_profile(), DEFAULT_PROFILE);
+        assert!(file.get_profile(DEFAULT_PROFILE).is_err());
+        assert_eq!(file.get_profile("dev").unwrap().sn_instance, "libertydev");
+        let file = ConfigFile {
+            default_profile: Some("prod".to_string()),
+            ..file
+        };
+        assert_eq!(file.resolve_profile(), "prod");
+        let (round_trip, version) =
+            ConfigFile::from_toml_str(&toml::to_string(&file).unwrap()).unwrap();
+        assert_eq!(version, config::CONFIG_VERSION);
+        assert_eq!(round_trip.default_profile.as_deref(), Some("prod"));
+        assert_eq!(round_trip.profiles.len(), 2);
+
+        assert_eq!(config::parse_profile_name("dev_2-eu").unwrap(), "dev_2-eu");
+        for name in ["", "../../x", "dev.prod", "a b"] {
+            assert!(config::parse_profile_name(name).is_err(), "{}", name);
+        }
+        let err =
+            ConfigFile::from_toml_str("version = 2\ndefault_profile = \"../x\"\n").unwrap_err();
+        assert!(err.to_string().contains("invalid profile name"), "{}", err);
+    }
+
+    #[test]
+    fn test_config_parse_errors() {
+        use config::ConfigFile;
+        let profile =
+            "instance = \"i\"\nsn_instance = \"liberty\"\nsn_username = \"u\"\nbin = \"Linux\"\n";
+        let error = |contents: &str| ConfigFile::from_toml_str(contents).unwrap_err().to_string();
+        let typo = error(&format!(
+            "version = 2\n\n[profiles.dev]\n{}sn_pasword = \"x\"\n",
+            profile
+        ));
+        assert!(typo.contains("line 8"), "{}", typo);
+        assert!(typo.contains("unknown field `sn_pasword`"), "{}", typo);
+        let missing = error("instance = \"i\"\nsn_instance = \"liberty\"\n");
+        assert!(
+            missing.contains("missing field `sn_username`"),
+            "{}",
+            missing
+        );
+        let bad_value = error(&format!("{}[retry]\nmax_attempts = \"4\"\n", profile));
+        assert!(bad_value.contains("line 6"), "{}", bad_value);
+        assert!(error("bin = \"Linux").contains("line 1"));
+        assert!(error(&format!("version = 9\n[profiles.dev]\n{}", profile)).contains("newer"));
+
+        // Settings added later are optional
+        let (file, _) =
+            ConfigFile::from_toml_str(&format!("version = 2\n[profiles.dev]\n{}", profile))
+                .unwrap();
+        let config = file.get_profile("dev").unwrap();
+        assert_eq!(
+            config.retry,
+            crate::elasticnow::retry::RetryPolicy::default()
+        );
+        assert_eq!(
+            config.credential_store,
+            credentials::CredentialStore::Config
+        );
+        assert_eq!(config.daily_target, None);
+    }
+
+    #[test]
+    fn test_config_get_set_values() {
+        let mut config = config::Config {
+            sn_password: "hunter2".to_string(),
+            bin: "Linux".to_string(),
+            ..Default::default()
+        };
+        config.set_value("bin", "Windows").unwrap();
+        config.set_value("daily_target", "7").unwrap();
+        config.set_value("retry.timeout_secs", "30").unwrap();
+        config.set_value("oauth.client_id", "client").unwrap();
+        config.set_value("credential_store", "encrypted").unwrap();
+        assert_eq!(config.bin, "Windows");
+        assert_eq!(config.daily_target, Some(7.0));
+        assert_eq!(config.retry.timeout_secs, 30);
+        assert_eq!(config.oauth.as_ref().unwrap().client_id, "client");
+        assert_eq!(
+            config.credential_store,
+            credentials::CredentialStore::Encrypted
+        );
+        assert!(config.set_value("auth", "kerberos").is_err());
+        assert!(config.set_value("colour", "blue").is_err());
+        assert!(config.set_value("sn_password", "letmein").is_err());
+        assert!(config.set_value("oauth.client_secret", "secret").is_err());
+        assert_eq!(config.sn_password, "hunter2");
+        assert_eq!(
+            config.get_value("retry.timeout_secs").unwrap(),
+            Some(toml::Value::Integer(30))
+        );
+        assert_eq!(config.get_value("password_command").unwrap(), None);
+        let masked = config.masked();
+        assert_eq!(masked.sn_password, "********");
+        assert_eq!(masked.id, "");
+    }
+
+    #[test]
+    fn test_picker_filter_and_render() {
+        use picker::{filter_items, render, PickerItem};
+        let items = vec![
+            PickerItem {
+                label: "RITM0000001: Patch web servers".to_string(),
+                preview: vec!["Priority: 4".to_string()],
+            },
+            PickerItem::new("RITM0000002: Renew certificates"),
+            PickerItem::new("INC0000003: Web server down"),
+        ];
+        assert_eq!(filter_items(&items, ""), vec![0, 1, 2]);
+        assert_eq!(filter_items(&items, "cert"), vec![1]);
+        let web = filter_items(&items, "WEB");
+        assert_eq!(web.len(), 2);
+        assert!(!web.contains(&1));
+        assert!(filter_items(&items, "zzz").is_empty());
+
+        console::set_colors_enabled(false);
+        let lines = render("Ticket:", &items, "", &[0, 1, 2], 0, 20);
+        assert_eq!(lines[1], "> RITM0000001: Patc…");
+        assert_eq!(lines[2], "  RITM0000002: Rene…");
+        assert_eq!(lines.last().unwrap(), "Priority: 4");
+        let lines = render("Ticket:", &items, "zzz", &[], 0, 80);
+        assert_eq!(lines, vec!["? Ticket: zzz", "  No matches"]);
+    }
+
+    #[test]
+    fn test_history_recent_and_favourites() {
+        use history::History;
+        let mut history = History::default();
+        history.record("sys1", "RITM0000001", "Patch web servers");
+        history.record("sys2", "RITM0000002", "Renew certificates");
+        history.record("sys1", "RITM0000001", "Patch all web servers");
+        history.tickets[1].last_used -= 60;
+        let recent = history.recent(5);
+        assert_eq!(recent[0].count, 2);
+        assert_eq!(recent[0].short_description, "Patch all web servers");
+        assert_eq!(recent[1].number, "RITM0000002");
+
+        history.add_favourite("sys9", "INC0000009", "On call");
+        history.add_favourite("sys2", "RITM0000002", "Renew certificates");
+        let labels: Vec<String> = history.picker_entries().iter().map(|e| e.label()).collect();
+        assert_eq!(
+            labels,
+            vec![
+                "★ INC0000009: On call",
+                "★ RITM0000002: Renew certificates",
+                "↺ RITM0000001: Patch all web servers",
+            ]
+        );
+        assert_eq!(history.favourites()[0].usage(), "Never used");
+
+        assert!(history.remove_favourite("inc0000009"));
+        assert!(!history.remove_favourite("INC0000009"));
+        assert!(history.remove_favourite("sys2"));
+        assert_eq!(history.tickets.len(), 2);
+        assert!(history.favourites().is_empty());
+
+        history.add_favourite("sys0", "RITM0000000", "Pinned");
+        for i in 0..60 {
+            history.record(&format!("task{}", i), &format!("TASK{:07}", i), "Task");
+        }
+        assert_eq!(history.tickets.len(), 51);
+        assert_eq!(history.favourites().len(), 1);
+    }
+
+    #[test]
+    fn test_split_parse_and_allocate() {
+        use timetrack::{allocate_split, parse_split, seconds_to_duration, Share};
+        let shares = parse_split("RITM1=50%, RITM2=25%,clerical=25%").unwrap();
+        assert_eq!(shares[0].target, "RITM1");
+        assert_eq!(shares[2].share, Share::Percent(25.0));
+        assert_eq!(
+            allocate_split(&shares, 4 * 3600).unwrap(),
+            vec![7200, 3600, 3600]
+        );
+
+        // Thirds of an hour round to whole minutes and still add up to the hour
+        let thirds = parse_split("a=33.3%,b=33.3%,c=33.4%").unwrap();
+        assert_eq!(
+            allocate_split(&thirds, 3600).unwrap(),
+            vec![1200, 1200, 1200]
+        );
+        let sevenths = parse_split("a=50%,b=50%").unwrap();
+        assert_eq!(allocate_split(&sevenths, 7 * 60).unwrap(), vec![240, 180]);
+        assert_eq!(allocate_split(&sevenths, 61).unwrap(), vec![31, 30]);
+
+        let durations = parse_split("RITM1=1h30m,clerical=30m").unwrap();
+        assert_eq!(durations[0].share, Share::Seconds(5400));
+        assert_eq!(allocate_split(&durations, 7200).unwrap(), vec![5400, 1800]);
+        assert!(allocate_split(&durations, 3600).is_err());
+
+        for invalid in [
+            "RITM1=50%",
+            "RITM1=50%,RITM2=1h",
+            "RITM1=50%,ritm1=50%",
+            "RITM1,RITM2=1h",
+            "RITM1=150%,RITM2=10%",
+            "RITM1=soon,RITM2=1h",
+        ] {
+            assert!(parse_split(invalid).is_err(), "{}", invalid);
+        }
+        assert!(allocate_split(&parse_split("a=60%,b=60%").unwrap(), 3600).is_err());
+        assert!(allocate_split(&parse_split("a=99.9%,b=0.1%").unwrap(), 3600).is_err());
+
+        assert_eq!(seconds_to_duration(5400), "1h30m");
+        assert_eq!(seconds_to_duration(3661), "1h1m1s");
+        assert_eq!(seconds_to_duration(45), "45s");
+    }
 }
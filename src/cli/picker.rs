@@ -0,0 +1,142 @@
+use console::{truncate_str, Key, Style, Term};
+use fuzzy_matcher::skim::SkimMatcherV2;
+use fuzzy_matcher::FuzzyMatcher;
+use std::io;
+
+// Rows of the list and of the preview shown at once
+const VISIBLE_ITEMS: usize = 10;
+const PREVIEW_LINES: usize = 8;
+
+/// An option of the picker with the lines previewed below the list while it is selected
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct PickerItem {
+    pub label: String,
+    pub preview: Vec<String>,
+}
+
+impl PickerItem {
+    pub fn new(label: &str) -> Self {
+        Self {
+            label: label.to_string(),
+            preview: Vec::new(),
+        }
+    }
+}
+
+// Indexes of the items whose label fuzzy matches query, best match first. Every item is kept in
+// its original order when the query is empty
+pub fn filter_items(items: &[PickerItem], query: &str) -> Vec<usize> {
+    if query.is_empty() {
+        return (0..items.len()).collect();
+    }
+    let matcher = SkimMatcherV2::default().ignore_case();
+    let mut scored: Vec<(i64, usize)> = items
+        .iter()
+        .enumerate()
+        .filter_map(|(i, item)| matcher.fuzzy_match(&item.label, query).map(|s| (s, i)))
+        .collect();
+    scored.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+    scored.into_iter().map(|(_, i)| i).collect()
+}
+
+// Lines of one frame: the prompt with the query, a window of the matches around the cursor and
+// the preview of the selected item, each cut to the terminal width
+pub fn render(
+    prompt: &str,
+    items: &[PickerItem],
+    query: &str,
+    matches: &[usize],
+    cursor: usize,
+    width: usize,
+) -> Vec<String> {
+    let selected_style = Style::new().cyan().bold();
+    let dim = Style::new().dim();
+    let fit = |line: &str| truncate_str(line, width, "…").to_string();
+    let mut lines = vec![fit(&format!(
+        "{} {} {}",
+        Style::new().green().apply_to("?"),
+        Style::new().bold().apply_to(prompt),
+        query
+    ))];
+    if matches.is_empty() {
+        lines.push(dim.apply_to("  No matches").to_string());
+        return lines;
+    }
+    let start = cursor.saturating_sub(VISIBLE_ITEMS - 1);
+    for (row, &index) in matches.iter().enumerate().skip(start).take(VISIBLE_ITEMS) {
+        if row == cursor {
+            let label = fit(&format!("> {}", items[index].label));
+            lines.push(selected_style.apply_to(label).to_string());
+        } else {
+            lines.push(fit(&format!("  {}", items[index].label)));
+        }
+    }
+    lines.push(
+        dim.apply_to(format!("  {}/{}", matches.len(), items.len()))
+            .to_string(),
+    );
+    let preview = &items[matches[cursor]].preview;
+    if !preview.is_empty() {
+        lines.push(dim.apply_to("─".repeat(width.min(60))).to_string());
+        lines.extend(preview.iter().take(PREVIEW_LINES).map(|l| fit(l)));
+    }
+    lines
+}
+
+// Lets the user type to filter the items and returns the index of the chosen one, or None when
+// cancelled with Esc or Ctrl-C
+pub fn pick(prompt: &str, items: &[PickerItem]) -> io::Result<Option<usize>> {
+    let term = Term::stderr();
+    if !term.is_term() {
+        return Err(io::Error::other("no terminal to choose in"));
+    }
+    term.hide_cursor()?;
+    let chosen = pick_in(&term, prompt, items);
+    term.show_cursor()?;
+    let chosen = chosen?;
+    if let Some(index) = chosen {
+        term.write_line(&format!(
+            "{} {} {}",
+            Style::new().green().apply_to("✔"),
+            Style::new().bold().apply_to(prompt),
+            Style::new().green().apply_to(&items[index].label)
+        ))?;
+    }
+    Ok(chosen)
+}
+
+fn pick_in(term: &Term, prompt: &str, items: &[PickerItem]) -> io::Result<Option<usize>> {
+    let mut query = String::new();
+    let mut cursor = 0;
+    let mut drawn = 0;
+    let chosen = loop {
+        let matches = filter_items(items, &query);
+        cursor = cursor.min(matches.len().saturating_sub(1));
+        let width = term.size().1 as usize;
+        let lines = render(prompt, items, &query, &matches, cursor, width);
+        term.clear_last_lines(drawn)?;
+        for line in &lines {
+            term.write_line(line)?;
+        }
+        drawn = lines.len();
+        match term.read_key()? {
+            Key::Enter if !matches.is_empty() => break Some(matches[cursor]),
+            Key::Escape | Key::CtrlC => break None,
+            Key::ArrowUp | Key::BackTab => cursor = cursor.saturating_sub(1),
+            Key::ArrowDown | Key::Tab if cursor + 1 < matches.len() => cursor += 1,
+            Key::PageUp => cursor = cursor.saturating_sub(VISIBLE_ITEMS),
+            Key::PageDown => cursor = (cursor + VISIBLE_ITEMS).min(matches.len().saturating_sub(1)),
+            Key::Backspace => {
+                query.pop();
+                cursor = 0;
+            }
+            Key::Char(c) if !c.is_control() => {
+                query.push(c);
+                cursor = 0;
+            }
+            _ => {}
+        }
+    };
+    term.clear_last_lines(drawn)?;
+    Ok(chosen)
+}
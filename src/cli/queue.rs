@@ -0,0 +1,58 @@
+use crate::cli::config::{get_profile_name, get_profile_path, make_dir_if_none};
+use crate::elasticnow::files::write_atomic;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::PathBuf;
+
+/// A time entry that could not be sent to ServiceNow and is waiting for `elasticnow sync`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct QueuedEntry {
+    /// sys_id of the ticket, unset when tracking without a ticket
+    pub sys_id: Option<String>,
+    /// u_category used when tracking without a ticket
+    pub category: Option<String>,
+    pub time_worked: String,
+    pub comment: String,
+    /// Day the time should be tracked for (YYYY-MM-DD)
+    pub date: String,
+    /// Error from the most recent failed attempt
+    pub last_error: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Queue {
+    #[serde(default)]
+    pub entries: Vec<QueuedEntry>,
+}
+
+impl Queue {
+    pub fn from_toml_file() -> io::Result<Self> {
+        let path = get_queue_path();
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let mut file = File::open(path)?;
+        let mut contents = String::new();
+        file.read_to_string(&mut contents)?;
+        toml::from_str(&contents).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn to_toml_file(&self) -> io::Result<()> {
+        make_dir_if_none();
+        let toml_string =
+            toml::to_string(self).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        write_atomic(&get_queue_path(), toml_string.as_bytes())
+    }
+
+    pub fn push(entry: QueuedEntry) -> io::Result<()> {
+        let mut queue = Self::from_toml_file()?;
+        queue.entries.push(entry);
+        queue.to_toml_file()
+    }
+}
+
+// Queued entries are kept per profile so they are synced to the instance they were meant for
+pub fn get_queue_path() -> PathBuf {
+    get_profile_path(&get_profile_name(), "queue", "toml")
+}
@@ -0,0 +1,64 @@
+use crate::cli::args::ReportBy;
+use crate::elasticnow::backend::{TicketBackend, TimeWorkedBackend};
+use crate::elasticnow::elasticnow::ChooseOptions;
+use crate::elasticnow::error::Result;
+use crate::elasticnow::servicenow_structs::Choice;
+use std::collections::HashMap;
+
+#[derive(Debug, Default, PartialEq)]
+pub struct TimeWorkedSummary {
+    /// Seconds worked for each group
+    pub time_worked: HashMap<String, i64>,
+    /// Seconds worked in the range, including time that could not be grouped
+    pub total: i64,
+}
+
+pub async fn summarize_time_worked<B: TimeWorkedBackend + TicketBackend>(
+    backend: &B,
+    user: &str,
+    since: &str,
+    until: &str,
+    by: ReportBy,
+    categories: &[Choice],
+) -> Result<TimeWorkedSummary> {
+    let tasks = backend.get_user_time_worked(since, until, user).await?;
+    let mut summary = TimeWorkedSummary::default();
+    let mut tasks_ids: HashMap<String, i64> = HashMap::new();
+    for time_work in tasks {
+        let time_in_seconds: i64 = time_work.time_in_seconds.parse().unwrap_or_default();
+        summary.total += time_in_seconds;
+        let key = match by {
+            ReportBy::Day => time_work.get_day(),
+            ReportBy::Category => time_work.get_nice_name_category(categories),
+            _ if time_work.task.is_empty() => time_work.get_nice_name_category(categories),
+            _ => {
+                *tasks_ids.entry(time_work.task).or_insert(0) += time_in_seconds;
+                continue;
+            }
+        };
+        *summary.time_worked.entry(key).or_insert(0) += time_in_seconds;
+    }
+    let keys = tasks_ids.keys().cloned().collect::<Vec<String>>();
+    if by == ReportBy::Ticket {
+        let ticket_names: HashMap<String, String> = backend
+            .get_tasks_by_sys_id(&keys)
+            .await?
+            .iter()
+            .map(|t| (t.get_id(), t.get_debug_string()))
+            .collect();
+        // Tickets that could not be looked up are still reported by sys_id
+        for (sys_id, time) in tasks_ids {
+            let name = ticket_names.get(&sys_id).cloned().unwrap_or(sys_id);
+            *summary.time_worked.entry(name).or_insert(0) += time;
+        }
+        return Ok(summary);
+    }
+    for cost_center in backend.get_tasks_cost_centers(&keys).await? {
+        let time = tasks_ids.get(&cost_center.task.value).unwrap_or(&0);
+        *summary
+            .time_worked
+            .entry(cost_center.cost_center.display_value)
+            .or_insert(0) += time;
+    }
+    Ok(summary)
+}
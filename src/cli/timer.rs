@@ -0,0 +1,79 @@
+use crate::cli::config::{get_config_dir, make_dir_if_none};
+use chrono::{Local, TimeZone};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Timer {
+    /// Unix timestamp of when the timer was started
+    pub started_at: i64,
+    pub comment: Option<String>,
+}
+
+impl Timer {
+    pub fn start(comment: Option<String>) -> Self {
+        Self {
+            started_at: Local::now().timestamp(),
+            comment,
+        }
+    }
+
+    pub fn from_toml_file() -> io::Result<Option<Self>> {
+        let path = get_timer_path();
+        if !path.exists() {
+            return Ok(None);
+        }
+        let mut file = File::open(path)?;
+        let mut contents = String::new();
+        file.read_to_string(&mut contents)?;
+        let timer: Timer =
+            toml::from_str(&contents).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        Ok(Some(timer))
+    }
+
+    pub fn to_toml_file(&self) -> io::Result<()> {
+        make_dir_if_none();
+        let toml_string = toml::to_string(self).unwrap_or_default();
+        let mut file = File::create(get_timer_path())?;
+        file.write_all(toml_string.as_bytes())?;
+        Ok(())
+    }
+
+    pub fn remove_file() -> io::Result<()> {
+        let path = get_timer_path();
+        if path.exists() {
+            std::fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+
+    pub fn elapsed_seconds(&self) -> i64 {
+        (Local::now().timestamp() - self.started_at).max(0)
+    }
+
+    pub fn started_at_string(&self) -> String {
+        Local
+            .timestamp_opt(self.started_at, 0)
+            .single()
+            .map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string())
+            .unwrap_or_default()
+    }
+}
+
+pub fn get_timer_path() -> PathBuf {
+    get_config_dir().join("timer.toml")
+}
+
+// Rounds elapsed seconds to the nearest minute (at least one) in the format accepted by timetrack
+pub fn seconds_to_time_worked(seconds: i64) -> String {
+    let minutes = ((seconds + 30) / 60).max(1);
+    let hours = minutes / 60;
+    let minutes = minutes % 60;
+    match (hours, minutes) {
+        (0, m) => format!("{}m", m),
+        (h, 0) => format!("{}h", h),
+        (h, m) => format!("{}h{}m", h, m),
+    }
+}
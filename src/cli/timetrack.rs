@@ -0,0 +1,434 @@
+use crate::cli::categories::find_category;
+use crate::cli::history::History;
+use crate::cli::queue::QueuedEntry;
+use crate::elasticnow::backend::{TicketBackend, TimeWorkedBackend};
+use crate::elasticnow::elasticnow::ChooseOptions;
+use crate::elasticnow::error::{Error, Result};
+use crate::elasticnow::servicenow::parse_time_worked;
+use crate::elasticnow::servicenow_structs::{Choice, ShortDescNumberID};
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TimeTarget {
+    /// sys_id of the ticket
+    Ticket(String),
+    /// u_category for time without a ticket
+    Category(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TimeEntry {
+    pub target: TimeTarget,
+    pub time_worked: String,
+    pub comment: String,
+    /// Day the time is tracked for (YYYY-MM-DD), ServiceNow defaults to today
+    pub date: Option<String>,
+}
+
+// Returns the sys_id of the created task_time_worked record
+pub async fn track_time<B: TimeWorkedBackend>(backend: &B, entry: &TimeEntry) -> Result<String> {
+    match &entry.target {
+        TimeTarget::Ticket(sys_id) => {
+            backend
+                .add_time_to_ticket(
+                    sys_id,
+                    &entry.time_worked,
+                    &entry.comment,
+                    entry.date.as_deref(),
+                )
+                .await
+        }
+        TimeTarget::Category(category) => {
+            backend
+                .add_time_to_no_tkt(
+                    category,
+                    &entry.time_worked,
+                    &entry.comment,
+                    entry.date.as_deref(),
+                )
+                .await
+        }
+    }
+}
+
+/// Ticket given with --sys-id or --ticket instead of searching
+#[derive(Debug, Clone, PartialEq)]
+pub enum TicketRef {
+    SysId(String),
+    Number(String),
+}
+
+impl fmt::Display for TicketRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TicketRef::SysId(sys_id) => write!(f, "sys_id {}", sys_id),
+            TicketRef::Number(number) => write!(f, "{}", number),
+        }
+    }
+}
+
+// Looks the ticket up so a wrong sys_id or number fails before any time is posted. Errors with
+// NotFound when there is no such ticket
+pub async fn find_ticket<B: TicketBackend>(
+    backend: &B,
+    ticket: &TicketRef,
+) -> Result<ShortDescNumberID> {
+    match ticket {
+        TicketRef::Number(number) => backend.get_ticket_by_number(number).await,
+        TicketRef::SysId(sys_id) => backend
+            .get_tasks_by_sys_id(std::slice::from_ref(sys_id))
+            .await?
+            .into_iter()
+            .next()
+            .ok_or(Error::NotFound {
+                service: "ServiceNow",
+                what: format!("ticket with sys_id {}", sys_id),
+            }),
+    }
+}
+
+// Value of the category matching name, by value or label
+pub fn resolve_category(categories: &[Choice], name: &str) -> Result<String> {
+    match find_category(categories, name) {
+        Some(found) => Ok(found.value.clone()),
+        None => Err(Error::Validation(format!(
+            "Unknown category {} (categories: {})",
+            name,
+            categories
+                .iter()
+                .map(|c| c.value.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ))),
+    }
+}
+
+// Counts the ticket in the history, looking it up when only the sys_id is known. The time is
+// already tracked, so a failed lookup only leaves the history unchanged. Returns whether the
+// ticket was recorded
+pub async fn record_ticket<B: TicketBackend>(
+    backend: &B,
+    history: &mut History,
+    sys_id: &str,
+    ticket: Option<ShortDescNumberID>,
+) -> bool {
+    let ticket = match ticket {
+        Some(ticket) => Some(ticket),
+        None => find_ticket(backend, &TicketRef::SysId(sys_id.to_string()))
+            .await
+            .ok(),
+    };
+    let Some(ticket) = ticket else {
+        tracing::debug!("Unable to look up {} for the history", sys_id);
+        return false;
+    };
+    history.record(sys_id, &ticket.number, &ticket.short_description);
+    true
+}
+
+/// Part of the time worked going to one target of --split
+#[derive(Debug, Clone, PartialEq)]
+pub enum Share {
+    Seconds(i64),
+    Percent(f64),
+}
+
+/// One `target=share` pair of --split, the target is a ticket number or a category as typed
+#[derive(Debug, Clone, PartialEq)]
+pub struct SplitShare {
+    pub target: String,
+    pub share: Share,
+}
+
+// Parses pairs such as "RITM0012345=50%,clerical=1h30m". Every share must be a duration or every
+// share a percentage
+pub fn parse_split(split: &str) -> Result<Vec<SplitShare>> {
+    let mut shares: Vec<SplitShare> = Vec::new();
+    for pair in split.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        let Some((target, share)) = pair.split_once('=') else {
+            return Err(Error::Validation(format!(
+                "Invalid split \"{}\", expected TARGET=DURATION or TARGET=PERCENT%",
+                pair
+            )));
+        };
+        let target = target.trim().to_string();
+        if target.is_empty() {
+            return Err(Error::Validation(format!("Missing target in \"{}\"", pair)));
+        }
+        if shares
+            .iter()
+            .any(|s| s.target.eq_ignore_ascii_case(&target))
+        {
+            return Err(Error::Validation(format!(
+                "{} is listed twice in the split",
+                target
+            )));
+        }
+        let share = match share.trim().strip_suffix('%') {
+            Some(percent) => match percent.trim().parse::<f64>() {
+                Ok(percent) if percent > 0.0 && percent <= 100.0 => Share::Percent(percent),
+                _ => {
+                    return Err(Error::Validation(format!(
+                        "Invalid percentage \"{}\" for {}",
+                        share, target
+                    )))
+                }
+            },
+            None => Share::Seconds(parse_time_worked(share)?),
+        };
+        shares.push(SplitShare { target, share });
+    }
+    if shares.len() < 2 {
+        return Err(Error::Validation(
+            "A split needs at least two TARGET=SHARE pairs".to_string(),
+        ));
+    }
+    let percentages = shares
+        .iter()
+        .filter(|s| matches!(s.share, Share::Percent(_)))
+        .count();
+    if percentages != 0 && percentages != shares.len() {
+        return Err(Error::Validation(
+            "Split by durations or by percentages, not both".to_string(),
+        ));
+    }
+    Ok(shares)
+}
+
+// Seconds for each share, adding up to exactly total. Percentages are rounded to whole minutes when
+// total is a whole number of minutes, and the minutes lost to rounding go to the shares that lost
+// the most
+pub fn allocate_split(shares: &[SplitShare], total: i64) -> Result<Vec<i64>> {
+    let mut percentages: Vec<f64> = Vec::new();
+    let mut durations: Vec<i64> = Vec::new();
+    for share in shares {
+        match share.share {
+            Share::Seconds(seconds) => durations.push(seconds),
+            Share::Percent(percent) => percentages.push(percent),
+        }
+    }
+    if !durations.is_empty() {
+        let sum: i64 = durations.iter().sum();
+        if sum != total {
+            return Err(Error::Validation(format!(
+                "Split durations add up to {} but the time worked is {}",
+                seconds_to_duration(sum),
+                seconds_to_duration(total)
+            )));
+        }
+        return Ok(durations);
+    }
+    let sum: f64 = percentages.iter().sum();
+    if (sum - 100.0).abs() > 0.01 {
+        return Err(Error::Validation(format!(
+            "Split percentages add up to {}% instead of 100%",
+            sum
+        )));
+    }
+    let unit = if total % 60 == 0 { 60 } else { 1 };
+    let units = total / unit;
+    let exact: Vec<f64> = percentages.iter().map(|p| units as f64 * p / sum).collect();
+    let mut allocated: Vec<i64> = exact.iter().map(|e| e.floor() as i64).collect();
+    let mut by_remainder: Vec<usize> = (0..exact.len()).collect();
+    by_remainder.sort_by(|&a, &b| {
+        (exact[b] - exact[b].floor())
+            .total_cmp(&(exact[a] - exact[a].floor()))
+            .then(a.cmp(&b))
+    });
+    let leftover = units - allocated.iter().sum::<i64>();
+    for &i in by_remainder.iter().take(leftover as usize) {
+        allocated[i] += 1;
+    }
+    if let Some(i) = allocated.iter().position(|&a| a == 0) {
+        return Err(Error::Validation(format!(
+            "The share of {} rounds to nothing",
+            shares[i].target
+        )));
+    }
+    Ok(allocated.into_iter().map(|a| a * unit).collect())
+}
+
+// Formats seconds as a duration parse_time_worked reads back exactly, e.g. 1h30m or 2m5s
+pub fn seconds_to_duration(seconds: i64) -> String {
+    let (hours, minutes, seconds) = (seconds / 3600, seconds % 3600 / 60, seconds % 60);
+    let mut duration = String::new();
+    if hours > 0 {
+        duration += &format!("{}h", hours);
+    }
+    if minutes > 0 {
+        duration += &format!("{}m", minutes);
+    }
+    if seconds > 0 || duration.is_empty() {
+        duration += &format!("{}s", seconds);
+    }
+    duration
+}
+
+/// Category or ticket a --split share or import row goes to
+#[derive(Debug, Clone)]
+pub struct ResolvedTarget {
+    pub target: TimeTarget,
+    /// Category label, or number and short description of the ticket
+    pub label: String,
+    /// Set for tickets, to count them in the history
+    pub ticket: Option<ShortDescNumberID>,
+}
+
+// Matches name to a category first and to a ticket number otherwise. Errors with Validation when
+// it is neither
+pub async fn resolve_target<B: TicketBackend>(
+    backend: &B,
+    categories: &[Choice],
+    name: &str,
+) -> Result<ResolvedTarget> {
+    if let Some(found) = find_category(categories, name) {
+        return Ok(ResolvedTarget {
+            target: TimeTarget::Category(found.value.clone()),
+            label: found.label.clone(),
+            ticket: None,
+        });
+    }
+    match backend.get_ticket_by_number(&name.to_uppercase()).await {
+        Ok(found) => Ok(ResolvedTarget {
+            target: TimeTarget::Ticket(found.get_id()),
+            label: found.get_debug_string(),
+            ticket: Some(found),
+        }),
+        Err(Error::NotFound { .. }) => Err(Error::Validation(format!(
+            "{} is neither a category nor a ticket",
+            name
+        ))),
+        Err(e) => Err(e),
+    }
+}
+
+// Parses and allocates the split and resolves each share, so nothing is posted when one is
+// invalid
+pub async fn resolve_split<B: TicketBackend>(
+    backend: &B,
+    categories: &[Choice],
+    split: &str,
+    time_worked: &str,
+    comment: &str,
+    date: Option<&str>,
+) -> Result<Vec<(TimeEntry, ResolvedTarget)>> {
+    let shares = parse_split(split)?;
+    let seconds = allocate_split(&shares, parse_time_worked(time_worked)?)?;
+    let mut resolved = Vec::new();
+    for (share, seconds) in shares.into_iter().zip(seconds) {
+        let target = resolve_target(backend, categories, &share.target).await?;
+        resolved.push((
+            TimeEntry {
+                target: target.target.clone(),
+                time_worked: seconds_to_duration(seconds),
+                comment: comment.to_string(),
+                date: date.map(str::to_string),
+            },
+            target,
+        ));
+    }
+    Ok(resolved)
+}
+
+/// A split that was not tracked, after deleting the records it had already created
+#[derive(Debug)]
+pub struct SplitFailure {
+    /// Index of the entry that could not be tracked
+    pub index: usize,
+    pub error: Error,
+    /// sys_ids of created records that could not be deleted again
+    pub not_rolled_back: Vec<String>,
+}
+
+// Tracks every entry or none of them, deleting the records already created when one fails.
+// Returns the sys_ids of the created records
+pub async fn track_split<B: TimeWorkedBackend>(
+    backend: &B,
+    entries: &[TimeEntry],
+) -> std::result::Result<Vec<String>, SplitFailure> {
+    let mut created: Vec<String> = Vec::new();
+    for (index, entry) in entries.iter().enumerate() {
+        match track_time(backend, entry).await {
+            Ok(sys_id) => created.push(sys_id),
+            Err(error) => {
+                let mut not_rolled_back: Vec<String> = Vec::new();
+                for sys_id in created {
+                    if let Err(e) = backend.delete_time_worked(&sys_id).await {
+                        tracing::debug!("Unable to delete {}: {}", sys_id, e);
+                        not_rolled_back.push(sys_id);
+                    }
+                }
+                return Err(SplitFailure {
+                    index,
+                    error,
+                    not_rolled_back,
+                });
+            }
+        }
+    }
+    Ok(created)
+}
+
+// Replays queued entries in order, returning the synced, the still failing and the uncertain
+// entries. Uncertain entries failed with a timeout or 5xx after ServiceNow may have tracked them,
+// so they are not queued again
+pub async fn sync_entries<B: TimeWorkedBackend>(
+    backend: &B,
+    entries: Vec<QueuedEntry>,
+) -> (Vec<QueuedEntry>, Vec<QueuedEntry>, Vec<QueuedEntry>) {
+    let mut synced: Vec<QueuedEntry> = Vec::new();
+    let mut failed: Vec<QueuedEntry> = Vec::new();
+    let mut uncertain: Vec<QueuedEntry> = Vec::new();
+    for mut entry in entries {
+        match track_time(backend, &entry.to_time_entry()).await {
+            Ok(_) => synced.push(entry),
+            Err(e) => {
+                entry.last_error = Some(e.to_string());
+                if e.is_transient() && !e.is_unsent() {
+                    uncertain.push(entry);
+                } else {
+                    failed.push(entry);
+                }
+            }
+        }
+    }
+    (synced, failed, uncertain)
+}
+
+impl QueuedEntry {
+    pub fn from_time_entry(entry: &TimeEntry, date: String, last_error: Option<String>) -> Self {
+        let (sys_id, category) = match &entry.target {
+            TimeTarget::Ticket(sys_id) => (Some(sys_id.clone()), None),
+            TimeTarget::Category(category) => (None, Some(category.clone())),
+        };
+        Self {
+            sys_id,
+            category,
+            time_worked: entry.time_worked.clone(),
+            comment: entry.comment.clone(),
+            date,
+            last_error,
+        }
+    }
+
+    pub fn to_time_entry(&self) -> TimeEntry {
+        let target = match &self.sys_id {
+            Some(sys_id) => TimeTarget::Ticket(sys_id.clone()),
+            None => TimeTarget::Category(self.category.clone().unwrap_or_default()),
+        };
+        TimeEntry {
+            target,
+            time_worked: self.time_worked.clone(),
+            comment: self.comment.clone(),
+            date: Some(self.date.clone()),
+        }
+    }
+
+    // sys_id or category the time was tracked against
+    pub fn get_target(&self) -> String {
+        self.sys_id
+            .clone()
+            .or(self.category.clone())
+            .unwrap_or_default()
+    }
+}
@@ -0,0 +1,136 @@
+// The CLI drives the clients from a single task, so the futures do not need to be Send
+#![allow(async_fn_in_trait)]
+
+use crate::elasticnow::elasticnow::{ElasticNow, SearchResult};
+use crate::elasticnow::error::Result;
+use crate::elasticnow::servicenow::ServiceNow;
+use crate::elasticnow::servicenow_structs::{
+    Choice, CostCenter, ShortDescNumberID, SysIdResult, TimeWorked,
+};
+
+/// Keyword search over active tickets in a bin
+pub trait TicketSearch {
+    async fn get_keyword_tickets(&self, keywords: &str, bin: &str) -> Result<Vec<SearchResult>>;
+}
+
+/// Looking up and creating tickets
+pub trait TicketBackend {
+    async fn get_all_tickets_in_bin(&self, bin: &str) -> Result<Vec<ShortDescNumberID>>;
+    async fn get_tasks_by_sys_id(&self, task_sys_ids: &[String]) -> Result<Vec<ShortDescNumberID>>;
+    // Errors with NotFound when no task has this number
+    async fn get_ticket_by_number(&self, number: &str) -> Result<ShortDescNumberID>;
+    // Returns the sys_id of the created ticket
+    async fn create_ticket(&self, assignment_group: &str, description: &str) -> Result<String>;
+}
+
+/// Tracking and reporting time worked
+pub trait TimeWorkedBackend {
+    // Both return the sys_id of the created task_time_worked record
+    async fn add_time_to_ticket(
+        &self,
+        ticket_id: &str,
+        time_worked: &str,
+        comment: &str,
+        created_for: Option<&str>,
+    ) -> Result<String>;
+    async fn add_time_to_no_tkt(
+        &self,
+        category: &str,
+        time_worked: &str,
+        comment: &str,
+        created_for: Option<&str>,
+    ) -> Result<String>;
+    async fn delete_time_worked(&self, sys_id: &str) -> Result<()>;
+    async fn get_user_time_worked(
+        &self,
+        start: &str,
+        end: &str,
+        user: &str,
+    ) -> Result<Vec<TimeWorked>>;
+    async fn get_tasks_cost_centers(&self, task_sys_ids: &[String]) -> Result<Vec<CostCenter>>;
+    // Choices for u_category, used when tracking time without a ticket
+    async fn get_time_worked_categories(&self) -> Result<Vec<Choice>>;
+}
+
+/// Standard change templates
+pub trait ChangeBackend {
+    async fn search_std_chg(&self, name: &str) -> Result<Vec<SysIdResult>>;
+    // Returns the sys_id of the created CHG
+    async fn create_std_chg_from_template(
+        &self,
+        template_sys_id: &str,
+        assignment_group: &str,
+    ) -> Result<String>;
+}
+
+impl TicketSearch for ElasticNow {
+    async fn get_keyword_tickets(&self, keywords: &str, bin: &str) -> Result<Vec<SearchResult>> {
+        ElasticNow::get_keyword_tickets(self, keywords, bin).await
+    }
+}
+
+impl TicketBackend for ServiceNow {
+    async fn get_all_tickets_in_bin(&self, bin: &str) -> Result<Vec<ShortDescNumberID>> {
+        ServiceNow::get_all_tickets_in_bin(self, bin).await
+    }
+    async fn get_tasks_by_sys_id(&self, task_sys_ids: &[String]) -> Result<Vec<ShortDescNumberID>> {
+        ServiceNow::get_tasks_by_sys_id(self, task_sys_ids).await
+    }
+    async fn get_ticket_by_number(&self, number: &str) -> Result<ShortDescNumberID> {
+        ServiceNow::get_ticket_by_number(self, number).await
+    }
+    async fn create_ticket(&self, assignment_group: &str, description: &str) -> Result<String> {
+        ServiceNow::create_ticket(self, assignment_group, description).await
+    }
+}
+
+impl TimeWorkedBackend for ServiceNow {
+    async fn add_time_to_ticket(
+        &self,
+        ticket_id: &str,
+        time_worked: &str,
+        comment: &str,
+        created_for: Option<&str>,
+    ) -> Result<String> {
+        ServiceNow::add_time_to_ticket(self, ticket_id, time_worked, comment, created_for).await
+    }
+    async fn add_time_to_no_tkt(
+        &self,
+        category: &str,
+        time_worked: &str,
+        comment: &str,
+        created_for: Option<&str>,
+    ) -> Result<String> {
+        ServiceNow::add_time_to_no_tkt(self, category, time_worked, comment, created_for).await
+    }
+    async fn delete_time_worked(&self, sys_id: &str) -> Result<()> {
+        ServiceNow::delete_time_worked(self, sys_id).await
+    }
+    async fn get_user_time_worked(
+        &self,
+        start: &str,
+        end: &str,
+        user: &str,
+    ) -> Result<Vec<TimeWorked>> {
+        ServiceNow::get_user_time_worked(self, start, end, user).await
+    }
+    async fn get_tasks_cost_centers(&self, task_sys_ids: &[String]) -> Result<Vec<CostCenter>> {
+        ServiceNow::get_tasks_cost_centers(self, task_sys_ids).await
+    }
+    async fn get_time_worked_categories(&self) -> Result<Vec<Choice>> {
+        ServiceNow::get_choices(self, "task_time_worked", "u_category").await
+    }
+}
+
+impl ChangeBackend for ServiceNow {
+    async fn search_std_chg(&self, name: &str) -> Result<Vec<SysIdResult>> {
+        ServiceNow::search_std_chg(self, name).await
+    }
+    async fn create_std_chg_from_template(
+        &self,
+        template_sys_id: &str,
+        assignment_group: &str,
+    ) -> Result<String> {
+        ServiceNow::create_std_chg_from_template(self, template_sys_id, assignment_group).await
+    }
+}
@@ -1,3 +1,6 @@
+use crate::elasticnow::error::{Error, Result};
+use crate::elasticnow::retry::RetryPolicy;
+use crate::elasticnow::servicenow::debug_resp_json_deserialize;
 use reqwest::Client;
 
 use serde::{Deserialize, Serialize};
@@ -34,20 +37,63 @@ impl ChooseOptions<SearchResult> for SearchResult {
     fn get_number(&self) -> String {
         self.source.number.clone()
     }
+    fn get_short_description(&self) -> String {
+        self.source.short_description.clone()
+    }
     fn get_id(&self) -> String {
         self.source.id.clone()
     }
+    fn get_preview(&self) -> Vec<String> {
+        let ticket = &self.source;
+        let mut preview = vec![
+            ticket.short_description.clone(),
+            format!(
+                "Priority: {}  Assigned to: {}  Opened: {}",
+                ticket.priority,
+                if ticket.assigned_to.is_empty() {
+                    "(nobody)"
+                } else {
+                    &ticket.assigned_to
+                },
+                ticket.sys_created_on
+            ),
+        ];
+        preview.extend(preview_lines(&ticket.description, 3));
+        // Journal fields list the newest entry first
+        let work_notes = preview_lines(&ticket.work_notes, 3);
+        if !work_notes.is_empty() {
+            preview.push("Work notes:".to_string());
+            preview.extend(work_notes);
+        }
+        preview
+    }
 }
 
 pub trait ChooseOptions<T> {
     fn get_debug_string(&self) -> String;
     fn get_id(&self) -> String;
     fn get_number(&self) -> String;
+    fn get_short_description(&self) -> String;
+    // Details shown while the option is selected, one line each
+    fn get_preview(&self) -> Vec<String>;
+}
+
+// Trimmed non-empty lines of text, for previewing multi-line fields
+pub fn preview_lines(text: &str, max_lines: usize) -> Vec<String> {
+    text.lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty())
+        .take(max_lines)
+        .map(str::to_string)
+        .collect()
 }
 
+const SERVICE: &str = "ElasticNow";
+
 pub struct ElasticNow {
     instance: String,
     pub client: Client,
+    pub retry: RetryPolicy,
 }
 
 impl ElasticNow {
@@ -65,29 +111,32 @@ impl ElasticNow {
         Self {
             instance: instance.to_owned(),
             client,
+            retry: RetryPolicy::default(),
         }
     }
-    pub async fn check_auth(&self) -> Result<(), Box<dyn std::error::Error>> {
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+    pub async fn check_auth(&self) -> Result<()> {
         let resp = self
-            .client
-            .get(self.instance.to_owned() + "/cli/login")
-            .send()
+            .retry
+            .send(SERVICE, true, || {
+                self.client.get(self.instance.to_owned() + "/cli/login")
+            })
             .await?;
-        if !resp.status().is_success() {
-            return Err(format!("HTTP Error while querying ElasticNow: {}", resp.status()).into());
-        }
+        Error::check_status(SERVICE, resp)?;
         Ok(())
     }
-    async fn post_json(
-        &self,
-        path: &str,
-        body: serde_json::Value,
-    ) -> Result<reqwest::Response, reqwest::Error> {
+    // Searches are sent as posts but do not change anything, so they are safe to resend
+    async fn post_json(&self, path: &str, body: serde_json::Value) -> Result<reqwest::Response> {
         tracing::debug!("Getting {}", path);
-        self.client
-            .post(self.instance.to_owned() + path)
-            .json(&body)
-            .send()
+        self.retry
+            .send(SERVICE, true, || {
+                self.client
+                    .post(self.instance.to_owned() + path)
+                    .json(&body)
+            })
             .await
     }
 
@@ -95,7 +144,7 @@ impl ElasticNow {
         &self,
         keywords: &str,
         bin: &str,
-    ) -> Result<Vec<SearchResult>, Box<dyn std::error::Error>> {
+    ) -> Result<Vec<SearchResult>> {
         let body = serde_json::json!(
             [
                 {
@@ -114,14 +163,9 @@ impl ElasticNow {
         let resp = self
             .post_json(&format!("/tickets/{}", keywords), body)
             .await?;
+        let resp = Error::check_status(SERVICE, resp)?;
 
-        if !resp.status().is_success() {
-            return Err(
-                format!("HTTP Error while querying ElasticNow: {:?}", resp.status()).into(),
-            );
-        }
-
-        let search_results: Vec<SearchResult> = resp.json().await?;
+        let search_results: Vec<SearchResult> = debug_resp_json_deserialize(resp).await?;
 
         Ok(search_results)
     }
@@ -0,0 +1,135 @@
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use reqwest::{Response, StatusCode};
+use std::fmt;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug)]
+pub enum Error {
+    /// The service rejected the credentials (401 or 403)
+    Auth {
+        service: &'static str,
+        status: StatusCode,
+    },
+    /// A 404 or a lookup that returned no records
+    NotFound { service: &'static str, what: String },
+    /// 429, with the delay from the Retry-After header in seconds when one was sent
+    RateLimited {
+        service: &'static str,
+        retry_after: Option<u64>,
+    },
+    /// Any other unsuccessful status code
+    Status {
+        service: &'static str,
+        status: StatusCode,
+    },
+    /// The request could not be sent or the response could not be read
+    Transport(reqwest::Error),
+    /// The response was not the expected JSON, body holds the raw response
+    Json {
+        error: serde_json::Error,
+        body: String,
+    },
+    /// Input was rejected before anything was sent
+    Validation(String),
+}
+
+impl Error {
+    // Maps an unsuccessful response to an error, successful responses are returned unchanged
+    pub fn check_status(service: &'static str, resp: Response) -> Result<Response> {
+        let status = resp.status();
+        if status.is_success() {
+            return Ok(resp);
+        }
+        Err(match status {
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => Error::Auth { service, status },
+            StatusCode::NOT_FOUND => Error::NotFound {
+                service,
+                what: resp.url().path().to_string(),
+            },
+            StatusCode::TOO_MANY_REQUESTS => Error::RateLimited {
+                service,
+                retry_after: retry_after(resp.headers()),
+            },
+            _ => Error::Status { service, status },
+        })
+    }
+
+    pub fn is_auth(&self) -> bool {
+        matches!(self, Error::Auth { .. })
+    }
+
+    // True when the same request may succeed later (network errors, 429 and 5xx)
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Error::Transport(e) => e.is_connect() || e.is_timeout() || e.is_request(),
+            Error::RateLimited { .. } => true,
+            Error::Status { status, .. } => status.is_server_error(),
+            _ => false,
+        }
+    }
+
+    // True when the server did not process the request (connect errors and 429), so sending it
+    // again cannot create a duplicate. A timeout or 5xx may come after the record was created
+    pub fn is_unsent(&self) -> bool {
+        match self {
+            Error::Transport(e) => e.is_connect(),
+            Error::RateLimited { .. } => true,
+            _ => false,
+        }
+    }
+}
+
+// Seconds from a Retry-After header, HTTP dates are not supported
+pub fn retry_after(headers: &HeaderMap) -> Option<u64> {
+    headers
+        .get(RETRY_AFTER)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse().ok())
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Auth { service, status } => {
+                write!(
+                    f,
+                    "Authentication failed while querying {}: {}",
+                    service, status
+                )
+            }
+            Error::NotFound { service, what } => write!(f, "Not found in {}: {}", service, what),
+            Error::RateLimited {
+                service,
+                retry_after: Some(seconds),
+            } => write!(
+                f,
+                "Rate limited by {}, retry after {} seconds",
+                service, seconds
+            ),
+            Error::RateLimited { service, .. } => write!(f, "Rate limited by {}", service),
+            Error::Status { service, status } => {
+                write!(f, "HTTP Error while querying {}: {}", service, status)
+            }
+            Error::Transport(e) => write!(f, "HTTP transport error: {}", e),
+            Error::Json { error, body } => write!(f, "JSON error: {} \n{}", error, body),
+            Error::Validation(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Transport(e) => Some(e),
+            Error::Json { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(e: reqwest::Error) -> Self {
+        Error::Transport(e)
+    }
+}
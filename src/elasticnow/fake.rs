@@ -0,0 +1,322 @@
+use crate::elasticnow::backend::{ChangeBackend, TicketBackend, TicketSearch, TimeWorkedBackend};
+use crate::elasticnow::elasticnow::{SearchResult, Ticket};
+use crate::elasticnow::error::{Error, Result};
+use crate::elasticnow::servicenow::parse_time_worked;
+use crate::elasticnow::servicenow_structs::{
+    Choice, CostCenter, DisplayAndValue, ShortDescNumberID, SysIdResult, TimeWorked,
+};
+use chrono::Local;
+use reqwest::StatusCode;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+#[derive(Debug, Clone, Default)]
+pub struct FakeTicket {
+    pub sys_id: String,
+    pub number: String,
+    pub short_description: String,
+    pub assignment_group: String,
+    pub cost_center: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct FakeTimeEntry {
+    pub sys_id: String,
+    pub user: String,
+    pub time_worked: TimeWorked,
+    pub comment: String,
+}
+
+/// In-memory stand-in for ServiceNow and ElasticNow, used to test flows without an instance
+#[derive(Debug, Default)]
+pub struct FakeBackend {
+    /// User recorded as sys_created_by on new time entries
+    pub user: String,
+    pub tickets: Mutex<Vec<FakeTicket>>,
+    pub time_entries: Mutex<Vec<FakeTimeEntry>>,
+    pub std_chg_templates: Vec<SysIdResult>,
+    /// u_category choices
+    pub categories: Vec<Choice>,
+    /// Created changes as (template sys_id, assignment group)
+    pub changes: Mutex<Vec<(String, String)>>,
+    /// When set every call fails with a connect error like an unreachable instance
+    pub offline: AtomicBool,
+    /// When set adding time fails with a 400 once this many entries exist, like a rejected record
+    pub time_entry_limit: Mutex<Option<usize>>,
+    /// Time entries ever added, so sys_ids stay unique after deletes
+    pub time_entry_count: AtomicUsize,
+}
+
+impl FakeBackend {
+    pub fn new(user: &str) -> Self {
+        Self {
+            user: user.to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_ticket(self, ticket: FakeTicket) -> Self {
+        self.tickets.lock().unwrap().push(ticket);
+        self
+    }
+
+    pub fn set_offline(&self, offline: bool) {
+        self.offline.store(offline, Ordering::SeqCst);
+    }
+
+    // Offline calls fail with a real connect error, from a port nothing listens on
+    async fn check_online(&self) -> Result<()> {
+        if !self.offline.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+        let closed = std::net::TcpListener::bind("127.0.0.1:0")
+            .and_then(|listener| listener.local_addr())
+            .expect("no free local port");
+        let client = reqwest::Client::builder().no_proxy().build()?;
+        match client.get(format!("http://{}", closed)).send().await {
+            Ok(_) => panic!("{} should not accept connections", closed),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn set_time_entry_limit(&self, limit: Option<usize>) {
+        *self.time_entry_limit.lock().unwrap() = limit;
+    }
+
+    async fn add_time(
+        &self,
+        task: &str,
+        category: &str,
+        time_worked: &str,
+        comment: &str,
+        created_for: Option<&str>,
+    ) -> Result<String> {
+        self.check_online().await?;
+        let seconds = parse_time_worked(time_worked)?;
+        let created_for = created_for
+            .map(|d| d.to_string())
+            .unwrap_or(Local::now().format("%Y-%m-%d").to_string());
+        let mut time_entries = self.time_entries.lock().unwrap();
+        let limit = *self.time_entry_limit.lock().unwrap();
+        if limit.is_some_and(|limit| time_entries.len() >= limit) {
+            return Err(Error::Status {
+                service: "ServiceNow",
+                status: StatusCode::BAD_REQUEST,
+            });
+        }
+        let sys_id = format!(
+            "fake_time_{}",
+            self.time_entry_count.fetch_add(1, Ordering::SeqCst)
+        );
+        time_entries.push(FakeTimeEntry {
+            sys_id: sys_id.clone(),
+            user: self.user.clone(),
+            time_worked: TimeWorked {
+                time_in_seconds: seconds.to_string(),
+                task: task.to_string(),
+                category: category.to_string(),
+                created_for,
+            },
+            comment: comment.to_string(),
+        });
+        Ok(sys_id)
+    }
+}
+
+impl FakeTicket {
+    fn to_short_desc(&self) -> ShortDescNumberID {
+        ShortDescNumberID {
+            short_description: self.short_description.clone(),
+            number: self.number.clone(),
+            sys_id: self.sys_id.clone(),
+        }
+    }
+}
+
+impl TicketSearch for FakeBackend {
+    async fn get_keyword_tickets(&self, keywords: &str, bin: &str) -> Result<Vec<SearchResult>> {
+        self.check_online().await?;
+        let keywords = keywords.to_lowercase();
+        Ok(self
+            .tickets
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|t| t.assignment_group == bin)
+            .filter(|t| t.short_description.to_lowercase().contains(&keywords))
+            .map(|t| SearchResult {
+                score: 1.0,
+                source: Ticket {
+                    comments: String::new(),
+                    work_notes: String::new(),
+                    description: t.short_description.clone(),
+                    short_description: t.short_description.clone(),
+                    active: "true".to_string(),
+                    assigned_to: String::new(),
+                    assignment_group: t.assignment_group.clone(),
+                    sys_created_on: String::new(),
+                    id: t.sys_id.clone(),
+                    self_link: String::new(),
+                    priority: "4".to_string(),
+                    number: t.number.clone(),
+                    type_: "sc_req_item".to_string(),
+                },
+            })
+            .collect())
+    }
+}
+
+impl TicketBackend for FakeBackend {
+    async fn get_all_tickets_in_bin(&self, bin: &str) -> Result<Vec<ShortDescNumberID>> {
+        self.check_online().await?;
+        Ok(self
+            .tickets
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|t| t.assignment_group == bin)
+            .map(FakeTicket::to_short_desc)
+            .collect())
+    }
+    async fn get_tasks_by_sys_id(&self, task_sys_ids: &[String]) -> Result<Vec<ShortDescNumberID>> {
+        self.check_online().await?;
+        Ok(self
+            .tickets
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|t| task_sys_ids.contains(&t.sys_id))
+            .map(FakeTicket::to_short_desc)
+            .collect())
+    }
+    async fn get_ticket_by_number(&self, number: &str) -> Result<ShortDescNumberID> {
+        self.check_online().await?;
+        self.tickets
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|t| t.number == number)
+            .map(FakeTicket::to_short_desc)
+            .ok_or(Error::NotFound {
+                service: "ServiceNow",
+                what: format!("ticket {}", number),
+            })
+    }
+    async fn create_ticket(&self, assignment_group: &str, description: &str) -> Result<String> {
+        self.check_online().await?;
+        let mut tickets = self.tickets.lock().unwrap();
+        let ticket = FakeTicket {
+            sys_id: format!("fake_sys_id_{}", tickets.len()),
+            number: format!("RITM{:07}", tickets.len()),
+            short_description: description.to_string(),
+            assignment_group: assignment_group.to_string(),
+            cost_center: None,
+        };
+        let sys_id = ticket.sys_id.clone();
+        tickets.push(ticket);
+        Ok(sys_id)
+    }
+}
+
+impl TimeWorkedBackend for FakeBackend {
+    async fn add_time_to_ticket(
+        &self,
+        ticket_id: &str,
+        time_worked: &str,
+        comment: &str,
+        created_for: Option<&str>,
+    ) -> Result<String> {
+        self.add_time(ticket_id, "", time_worked, comment, created_for)
+            .await
+    }
+    async fn add_time_to_no_tkt(
+        &self,
+        category: &str,
+        time_worked: &str,
+        comment: &str,
+        created_for: Option<&str>,
+    ) -> Result<String> {
+        self.add_time("", category, time_worked, comment, created_for)
+            .await
+    }
+    async fn delete_time_worked(&self, sys_id: &str) -> Result<()> {
+        self.check_online().await?;
+        let mut time_entries = self.time_entries.lock().unwrap();
+        let count = time_entries.len();
+        time_entries.retain(|e| e.sys_id != sys_id);
+        if time_entries.len() == count {
+            return Err(Error::NotFound {
+                service: "ServiceNow",
+                what: format!("task_time_worked {}", sys_id),
+            });
+        }
+        Ok(())
+    }
+    // Dates are compared as YYYY-MM-DD strings, so they must be zero padded
+    async fn get_user_time_worked(
+        &self,
+        start: &str,
+        end: &str,
+        user: &str,
+    ) -> Result<Vec<TimeWorked>> {
+        self.check_online().await?;
+        Ok(self
+            .time_entries
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|e| e.user == user)
+            .map(|e| e.time_worked.clone())
+            .filter(|t| t.get_day().as_str() >= start && t.get_day().as_str() <= end)
+            .collect())
+    }
+    async fn get_tasks_cost_centers(&self, task_sys_ids: &[String]) -> Result<Vec<CostCenter>> {
+        self.check_online().await?;
+        Ok(self
+            .tickets
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|t| task_sys_ids.contains(&t.sys_id))
+            .filter_map(|t| {
+                let cost_center = t.cost_center.clone()?;
+                Some(CostCenter {
+                    cost_center: DisplayAndValue {
+                        display_value: cost_center.clone(),
+                        value: cost_center,
+                    },
+                    task: DisplayAndValue {
+                        display_value: t.number.clone(),
+                        value: t.sys_id.clone(),
+                    },
+                })
+            })
+            .collect())
+    }
+    async fn get_time_worked_categories(&self) -> Result<Vec<Choice>> {
+        self.check_online().await?;
+        Ok(self.categories.clone())
+    }
+}
+
+impl ChangeBackend for FakeBackend {
+    async fn search_std_chg(&self, name: &str) -> Result<Vec<SysIdResult>> {
+        self.check_online().await?;
+        Ok(self
+            .std_chg_templates
+            .iter()
+            .filter(|t| t.sys_name.as_deref().unwrap_or_default().contains(name))
+            .cloned()
+            .collect())
+    }
+    async fn create_std_chg_from_template(
+        &self,
+        template_sys_id: &str,
+        assignment_group: &str,
+    ) -> Result<String> {
+        self.check_online().await?;
+        let mut changes = self.changes.lock().unwrap();
+        changes.push((template_sys_id.to_string(), assignment_group.to_string()));
+        Ok(format!("fake_chg_{}", changes.len()))
+    }
+}
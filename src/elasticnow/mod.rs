@@ -1,4 +1,12 @@
+pub mod backend;
+#[allow(clippy::module_inception)]
 pub mod elasticnow;
+pub mod error;
+#[cfg(any(test, feature = "fake"))]
+pub mod fake;
+pub mod files;
+pub mod oauth;
+pub mod retry;
 pub mod servicenow;
 pub mod servicenow_structs;
 
@@ -14,13 +22,33 @@ mod tests {
     }
 
     #[test]
-    fn test_time_add_to_epoch_too_many_hours() {
+    fn test_time_add_to_epoch_multi_day() {
         assert_eq!(
-            servicenow::time_add_to_epoch("20h0m")
-                .unwrap_err()
-                .to_string(),
-            "Invalid time format. Values must be below 20 for hours, 60 for minutes"
+            servicenow::time_add_to_epoch("20h0m").unwrap(),
+            "1970-01-01+20:00:00"
+        );
+        assert_eq!(
+            servicenow::time_add_to_epoch("50h").unwrap(),
+            "1970-01-03+02:00:00"
+        );
+    }
+
+    #[test]
+    fn test_time_add_to_epoch_too_long() {
+        assert_eq!(
+            servicenow::time_add_to_epoch("168h").unwrap(),
+            "1970-01-08+00:00:00"
         );
+        for input in ["168h1m", "9999999999h", "99999999999999999999m", "1e300h"] {
+            assert!(
+                matches!(
+                    servicenow::time_add_to_epoch(input),
+                    Err(error::Error::Validation(_))
+                ),
+                "{}",
+                input
+            );
+        }
     }
 
     #[test]
@@ -29,7 +57,39 @@ mod tests {
             servicenow::time_add_to_epoch("0h60m")
                 .unwrap_err()
                 .to_string(),
-            "Invalid time format. Values must be below 20 for hours, 60 for minutes"
+            "Invalid time format \"0h60m\". Minutes and seconds must be below 60 when combined with a larger unit"
+        );
+    }
+
+    #[test]
+    fn test_time_formats() {
+        for (input, seconds) in [
+            ("1.5h", 5400),
+            ("90m", 5400),
+            ("1h30", 5400),
+            ("1:30", 5400),
+            ("1:30:15", 5415),
+            ("2h 15m", 8100),
+            (" 2h15m30s ", 8130),
+            ("45s", 45),
+        ] {
+            assert_eq!(
+                servicenow::parse_time_worked(input).unwrap(),
+                seconds,
+                "{}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_time_invalid_format() {
+        assert_eq!(
+            servicenow::time_add_to_epoch("1x").unwrap_err().to_string(),
+            format!(
+                "Invalid time format \"1x\". {}",
+                servicenow::TIME_FORMAT_HELP
+            )
         );
     }
     #[test]
@@ -55,4 +115,89 @@ mod tests {
             "Time worked must be greater than 0 minutes"
         );
     }
+
+    #[test]
+    fn test_instance_url() {
+        use servicenow::instance_url;
+        assert_eq!(instance_url("liberty"), "https://liberty.service-now.com");
+        assert_eq!(
+            instance_url("sn.example.edu:8443"),
+            "https://sn.example.edu:8443"
+        );
+        assert_eq!(
+            instance_url("http://127.0.0.1:8080/servicenow/"),
+            "http://127.0.0.1:8080/servicenow"
+        );
+        let sn_client = servicenow::ServiceNow::new("u", "p", "https://sn.example.edu/prefix");
+        assert_eq!(
+            sn_client.record_link("change_request", "abc"),
+            "https://sn.example.edu/prefix/change_request.do?sys_id=abc"
+        );
+    }
+
+    #[test]
+    fn test_next_link() {
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert(
+            reqwest::header::LINK,
+            "<https://x.service-now.com/api/now/table/task?sysparm_offset=0>;rel=\"first\",<https://x.service-now.com/api/now/table/task?sysparm_offset=1000>;rel=\"next\""
+                .parse()
+                .unwrap(),
+        );
+        assert_eq!(
+            servicenow::get_next_link(&headers).unwrap(),
+            "https://x.service-now.com/api/now/table/task?sysparm_offset=1000"
+        );
+        headers.remove(reqwest::header::LINK);
+        assert_eq!(servicenow::get_next_link(&headers), None);
+    }
+
+    #[test]
+    fn test_page_params() {
+        assert_eq!(
+            servicenow::with_page_params("/api/now/table/task?sysparm_fields=sys_id", 100, 200),
+            "/api/now/table/task?sysparm_fields=sys_id&sysparm_limit=100&sysparm_offset=200"
+        );
+    }
+
+    #[test]
+    fn test_backoff() {
+        let policy = retry::RetryPolicy {
+            base_delay_ms: 500,
+            ..Default::default()
+        };
+        assert_eq!(policy.backoff(1), std::time::Duration::from_millis(500));
+        assert_eq!(policy.backoff(3), std::time::Duration::from_secs(2));
+        assert_eq!(policy.backoff(40), std::time::Duration::from_secs(30));
+        let policy: retry::RetryPolicy = toml::from_str("max_attempts = 1").unwrap();
+        assert_eq!(policy.max_attempts, 1);
+        assert_eq!(policy.base_delay_ms, 500);
+    }
+
+    #[test]
+    fn test_error_kinds() {
+        let unavailable = error::Error::Status {
+            service: "ServiceNow",
+            status: reqwest::StatusCode::SERVICE_UNAVAILABLE,
+        };
+        assert!(unavailable.is_transient() && !unavailable.is_unsent());
+        let rate_limited = error::Error::RateLimited {
+            service: "ServiceNow",
+            retry_after: None,
+        };
+        assert!(rate_limited.is_transient() && rate_limited.is_unsent());
+        assert_eq!(
+            unavailable.to_string(),
+            "HTTP Error while querying ServiceNow: 503 Service Unavailable"
+        );
+        let forbidden = error::Error::Auth {
+            service: "ServiceNow",
+            status: reqwest::StatusCode::FORBIDDEN,
+        };
+        assert!(forbidden.is_auth() && !forbidden.is_transient());
+        assert!(matches!(
+            servicenow::time_add_to_epoch("0m"),
+            Err(error::Error::Validation(_))
+        ));
+    }
 }
@@ -0,0 +1,171 @@
+use crate::elasticnow::error::{Error, Result};
+use crate::elasticnow::files::write_private;
+use crate::elasticnow::retry::RetryPolicy;
+use crate::elasticnow::servicenow::debug_resp_json_deserialize;
+use chrono::Utc;
+use reqwest::{Client, StatusCode};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use tokio::sync::Mutex;
+
+const SERVICE: &str = "ServiceNow OAuth";
+// Tokens are refreshed this many seconds before they expire
+const EXPIRY_LEEWAY_SECS: i64 = 60;
+
+/// Tokens from /oauth_token.do, cached so every command does not need a new one
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct OAuthToken {
+    pub access_token: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub refresh_token: Option<String>,
+    /// Unix timestamp the access token expires at
+    pub expires_at: i64,
+    /// Instance and user the tokens were issued for, cached tokens for others are ignored
+    pub instance: String,
+    pub username: String,
+}
+
+impl OAuthToken {
+    pub fn is_expired(&self) -> bool {
+        Utc::now().timestamp() + EXPIRY_LEEWAY_SECS >= self.expires_at
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct TokenResponse {
+    access_token: String,
+    refresh_token: Option<String>,
+    expires_in: i64,
+}
+
+/// OAuth password and refresh token grants against /oauth_token.do
+pub struct OAuth {
+    pub client_id: String,
+    pub client_secret: String,
+    /// File the tokens are cached in, nothing is cached when unset
+    pub cache_path: Option<PathBuf>,
+    // None until the cache is read or the first token is requested
+    token: Mutex<Option<OAuthToken>>,
+}
+
+impl OAuth {
+    pub fn new(client_id: &str, client_secret: &str, cache_path: Option<PathBuf>) -> Self {
+        Self {
+            client_id: client_id.to_owned(),
+            client_secret: client_secret.to_owned(),
+            cache_path,
+            token: Mutex::new(None),
+        }
+    }
+
+    // Returns a valid access token, refreshing or requesting a new one when needed.
+    // With force the current access token is not trusted, e.g. after a 401
+    pub async fn access_token(
+        &self,
+        client: &Client,
+        retry: &RetryPolicy,
+        instance: &str,
+        username: &str,
+        password: &str,
+        force: bool,
+    ) -> Result<String> {
+        // Held while requesting so concurrent requests wait for a single new token
+        let mut token = self.token.lock().await;
+        if token.is_none() {
+            *token = self
+                .read_cache()
+                .filter(|t| t.instance == instance && t.username == username);
+        }
+        if let Some(current) = token.as_ref().filter(|t| !force && !t.is_expired()) {
+            return Ok(current.access_token.clone());
+        }
+        let refresh_token = token.as_ref().and_then(|t| t.refresh_token.clone());
+        let mut new_token = None;
+        if let Some(refresh_token) = refresh_token {
+            let params = [
+                ("grant_type", "refresh_token"),
+                ("client_id", &self.client_id),
+                ("client_secret", &self.client_secret),
+                ("refresh_token", &refresh_token),
+            ];
+            match self.request_token(client, retry, instance, &params).await {
+                Ok(refreshed) => new_token = Some(refreshed),
+                // Expired or revoked refresh tokens fall back to the password grant
+                Err(e) if e.is_auth() => {
+                    tracing::debug!("Unable to refresh OAuth token: {}", e)
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        let mut new_token = match new_token {
+            Some(new_token) => new_token,
+            None => {
+                let params = [
+                    ("grant_type", "password"),
+                    ("client_id", &self.client_id),
+                    ("client_secret", &self.client_secret),
+                    ("username", username),
+                    ("password", password),
+                ];
+                self.request_token(client, retry, instance, &params).await?
+            }
+        };
+        new_token.instance = instance.to_owned();
+        new_token.username = username.to_owned();
+        // ServiceNow does not always send a new refresh token when refreshing
+        if new_token.refresh_token.is_none() {
+            new_token.refresh_token = token.as_ref().and_then(|t| t.refresh_token.clone());
+        }
+        self.write_cache(&new_token);
+        let access_token = new_token.access_token.clone();
+        *token = Some(new_token);
+        Ok(access_token)
+    }
+
+    async fn request_token(
+        &self,
+        client: &Client,
+        retry: &RetryPolicy,
+        instance: &str,
+        params: &[(&str, &str)],
+    ) -> Result<OAuthToken> {
+        let path = format!("{}/oauth_token.do", instance);
+        tracing::debug!("Getting {}", path);
+        let resp = retry
+            .send(SERVICE, false, || client.post(&path).form(params))
+            .await?;
+        // ServiceNow answers a bad grant with 400 or 401
+        if resp.status() == StatusCode::BAD_REQUEST {
+            return Err(Error::Auth {
+                service: SERVICE,
+                status: resp.status(),
+            });
+        }
+        let resp = Error::check_status(SERVICE, resp)?;
+        let token = debug_resp_json_deserialize::<TokenResponse>(resp).await?;
+        Ok(OAuthToken {
+            access_token: token.access_token,
+            refresh_token: token.refresh_token,
+            expires_at: Utc::now().timestamp() + token.expires_in,
+            ..Default::default()
+        })
+    }
+
+    fn read_cache(&self) -> Option<OAuthToken> {
+        let contents = std::fs::read_to_string(self.cache_path.as_ref()?).ok()?;
+        toml::from_str(&contents).ok()
+    }
+
+    // A token that could not be cached is still used for this command
+    fn write_cache(&self, token: &OAuthToken) {
+        let Some(path) = &self.cache_path else {
+            return;
+        };
+        let written = toml::to_string(token)
+            .map_err(std::io::Error::other)
+            .and_then(|contents| write_private(path, contents.as_bytes()));
+        if let Err(e) = written {
+            tracing::warn!("Unable to cache OAuth token in {:?}: {}", path, e);
+        }
+    }
+}
@@ -0,0 +1,93 @@
+use crate::elasticnow::error::{retry_after, Error, Result};
+use reqwest::{RequestBuilder, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+
+// Longest backoff between two attempts, Retry-After is honoured even when it is longer
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// How failed requests are retried, read from the [retry] table in config.toml
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetryPolicy {
+    /// Attempts per request including the first one, 1 disables retries
+    pub max_attempts: u32,
+    /// Delay before the first retry, doubled for every retry after it
+    pub base_delay_ms: u64,
+    /// Time allowed for a request including every retry
+    pub timeout_secs: u64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 4,
+            base_delay_ms: 500,
+            timeout_secs: 120,
+        }
+    }
+}
+
+impl RetryPolicy {
+    // Delay before the given retry (1 for the first retry)
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let factor = 2u32.saturating_pow(retry.saturating_sub(1));
+        Duration::from_millis(self.base_delay_ms)
+            .saturating_mul(factor)
+            .min(MAX_BACKOFF)
+    }
+
+    // Sends the request built by `request` until it succeeds or the policy gives up.
+    // Only idempotent requests are retried after timeouts and 5xx, since the server may have
+    // already processed them. Connect errors and 429 are retried for every request
+    pub async fn send<F>(
+        &self,
+        service: &'static str,
+        idempotent: bool,
+        request: F,
+    ) -> Result<Response>
+    where
+        F: Fn() -> RequestBuilder,
+    {
+        let deadline = Instant::now() + Duration::from_secs(self.timeout_secs);
+        let mut attempt = 1;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let result = request().timeout(remaining).send().await;
+            let delay = match &result {
+                Err(e) if e.is_connect() || (idempotent && e.is_timeout()) => self.backoff(attempt),
+                Ok(resp) if resp.status() == StatusCode::TOO_MANY_REQUESTS => {
+                    match retry_after(resp.headers()) {
+                        Some(seconds) => Duration::from_secs(seconds),
+                        None => self.backoff(attempt),
+                    }
+                }
+                Ok(resp) if idempotent && resp.status().is_server_error() => self.backoff(attempt),
+                _ => return result.map_err(Error::from),
+            };
+            if attempt >= self.max_attempts || Instant::now() + delay > deadline {
+                return result.map_err(Error::from);
+            }
+            match &result {
+                Ok(resp) => tracing::warn!(
+                    "{} returned {}, retrying in {:?} ({}/{})",
+                    service,
+                    resp.status(),
+                    delay,
+                    attempt,
+                    self.max_attempts
+                ),
+                Err(e) => tracing::warn!(
+                    "Unable to reach {}: {}, retrying in {:?} ({}/{})",
+                    service,
+                    e,
+                    delay,
+                    attempt,
+                    self.max_attempts
+                ),
+            }
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+}
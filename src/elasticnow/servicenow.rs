@@ -1,142 +1,225 @@
+use crate::elasticnow::error::{Error, Result};
+use crate::elasticnow::oauth::OAuth;
+use crate::elasticnow::retry::RetryPolicy;
 use crate::elasticnow::servicenow_structs::{
-    CostCenter, SNResult, SysIdResult, TicketCreation, TimeWorked, UserGroupResult,
+    Choice, CostCenter, SNResult, SysIdResult, TicketCreation, TimeWorked, UserGroupResult,
 };
-use chrono::{TimeZone, Utc};
+use chrono::{DateTime, Duration};
+use futures::stream::{self, StreamExt};
 use regex::Regex;
-use reqwest::Client;
-use std::error::Error;
+use reqwest::{Client, RequestBuilder, StatusCode};
 use tracing::debug;
 
 use super::servicenow_structs::{CHGCreation, ShortDescNumberID};
 
+const SERVICE: &str = "ServiceNow";
+
 pub struct ServiceNow {
     username: String,
     password: String,
     instance: String,
     pub client: Client,
+    pub retry: RetryPolicy,
+    /// Bearer tokens are sent instead of basic auth when set
+    pub oauth: Option<OAuth>,
 }
 
 impl ServiceNow {
+    // instance is either the instance name (e.g. liberty) or a base url, see instance_url
     pub fn new(username: &str, password: &str, instance: &str) -> Self {
         let client = reqwest::Client::new();
-        let set_instance = format!("https://{}.service-now.com", instance);
         Self {
             username: username.to_owned(),
             password: password.to_owned(),
-            instance: set_instance,
+            instance: instance_url(instance),
             client,
+            retry: RetryPolicy::default(),
+            oauth: None,
+        }
+    }
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+    pub fn with_oauth(mut self, oauth: OAuth) -> Self {
+        self.oauth = Some(oauth);
+        self
+    }
+    // Sends with basic auth, or with an OAuth token that is renewed once if it is rejected
+    async fn send<F>(&self, idempotent: bool, request: F) -> Result<reqwest::Response>
+    where
+        F: Fn() -> RequestBuilder,
+    {
+        let Some(oauth) = &self.oauth else {
+            return self
+                .retry
+                .send(SERVICE, idempotent, || {
+                    request().basic_auth(&self.username, Some(&self.password))
+                })
+                .await;
+        };
+        let mut force = false;
+        loop {
+            let token = oauth
+                .access_token(
+                    &self.client,
+                    &self.retry,
+                    &self.instance,
+                    &self.username,
+                    &self.password,
+                    force,
+                )
+                .await?;
+            let resp = self
+                .retry
+                .send(SERVICE, idempotent, || request().bearer_auth(&token))
+                .await?;
+            if resp.status() != StatusCode::UNAUTHORIZED || force {
+                return Ok(resp);
+            }
+            tracing::debug!("OAuth token was rejected, requesting a new one");
+            force = true;
         }
     }
-    pub async fn get(&self, path: &str) -> Result<reqwest::Response, reqwest::Error> {
+    pub fn record_link(&self, table: &str, sys_id: &str) -> String {
+        record_link(&self.instance, table, sys_id)
+    }
+    pub async fn get(&self, path: &str) -> Result<reqwest::Response> {
         debug!("Getting {}", path);
-        self.client
-            .get(path)
-            .basic_auth(&self.username, Some(&self.password))
-            .send()
-            .await
+        self.send(true, || self.client.get(path)).await
     }
     pub async fn post_json(
         &self,
         path: &str,
         json: serde_json::Value,
-    ) -> Result<reqwest::Response, reqwest::Error> {
+    ) -> Result<reqwest::Response> {
         debug!("Getting {}", path);
-        self.client
-            .post(path)
-            .json(&json)
-            .basic_auth(&self.username, Some(&self.password))
-            .send()
+        // Posts create records, so they are only resent when the instance never processed them
+        self.send(false, || self.client.post(path).json(&json))
             .await
     }
-    pub async fn get_all_tickets_in_bin(
-        &self,
-        bin: &str,
-    ) -> Result<Vec<ShortDescNumberID>, Box<dyn Error>> {
+    pub async fn delete(&self, path: &str) -> Result<reqwest::Response> {
+        debug!("Deleting {}", path);
+        self.send(true, || self.client.delete(path)).await
+    }
+    pub async fn get_all_tickets_in_bin(&self, bin: &str) -> Result<Vec<ShortDescNumberID>> {
+        self.get_all_pages::<ShortDescNumberID>(&format!(
+            "{}/api/now/table/task?sysparm_fields=sys_id,short_description,number,assignment_group&sysparm_query=active=true^assignment_group.name={}",
+            self.instance, bin
+        ))
+        .await
+    }
+    pub async fn get_ticket_by_number(&self, number: &str) -> Result<ShortDescNumberID> {
         let resp = self
             .get(&format!(
-                "{}/api/now/table/task?sysparm_fields=sys_id,short_description,number,assignment_group&sysparm_query=active=true^assignment_group.name={}",
-                self.instance, bin
+                "{}/api/now/table/task?number={}&sysparm_limit=1&sysparm_fields=sys_id,short_description,number",
+                self.instance, number
             ))
             .await?;
-        if !resp.status().is_success() {
-            return Err(format!("HTTP Error while querying ServiceNow: {}", resp.status()).into());
-        }
-        let result = debug_resp_json_deserialize::<SNResult<Vec<ShortDescNumberID>>>(resp).await;
-        if result.is_err() {
-            let error_msg = format!("JSON error: {}", result.unwrap_err());
-            tracing::error!("{}", error_msg);
-            return Err(error_msg.into());
-        }
-        Ok(result.unwrap().result)
+        let resp = Error::check_status(SERVICE, resp)?;
+        let tickets = debug_resp_json_deserialize::<SNResult<Vec<ShortDescNumberID>>>(resp).await?;
+        tickets.result.into_iter().next().ok_or(Error::NotFound {
+            service: SERVICE,
+            what: format!("ticket {}", number),
+        })
     }
-    pub async fn get_user_group(&self, username: &str) -> Result<String, Box<dyn Error>> {
+    pub async fn get_user_group(&self, username: &str) -> Result<String> {
         let resp = self.get(&format!(
             "{}/api/now/table/sys_user?user_name={}&sysparm_limit=1&sysparm_display_value=true&sysparm_exclude_reference_link=true&sysparm_fields=u_default_group",
             self.instance, username
         ))
         .await?;
+        let resp = Error::check_status(SERVICE, resp)?;
 
-        if !resp.status().is_success() {
-            return Err(format!("HTTP Error while querying ServiceNow: {}", resp.status()).into());
+        let user_response =
+            debug_resp_json_deserialize::<SNResult<Vec<UserGroupResult>>>(resp).await?;
+        match user_response.result.first() {
+            Some(user) => Ok(user.default_group.to_owned()),
+            None => Err(Error::NotFound {
+                service: SERVICE,
+                what: format!("user {}", username),
+            }),
         }
-
-        let user_response = resp.json::<SNResult<Vec<UserGroupResult>>>().await?;
-
-        Ok(user_response.result[0].default_group.to_owned())
+    }
+    // Errors with NotFound when no assignment group has this name
+    pub async fn check_group(&self, name: &str) -> Result<()> {
+        let resp = self
+            .get(&format!(
+                "{}/api/now/table/sys_user_group?name={}&sysparm_limit=1&sysparm_fields=sys_id",
+                self.instance, name
+            ))
+            .await?;
+        let resp = Error::check_status(SERVICE, resp)?;
+        let groups = debug_resp_json_deserialize::<SNResult<Vec<SysIdResult>>>(resp).await?;
+        if groups.result.is_empty() {
+            return Err(Error::NotFound {
+                service: SERVICE,
+                what: format!("assignment group {}", name),
+            });
+        }
+        Ok(())
     }
     pub async fn add_time_to_ticket(
         &self,
         ticket_id: &str,
         time_worked: &str,
         comment: &str,
-    ) -> Result<(), Box<dyn Error>> {
+        created_for: Option<&str>,
+    ) -> Result<String> {
         let time_worked = time_add_to_epoch(time_worked)?;
-        let post_body = serde_json::json!({
+        let mut post_body = serde_json::json!({
             "time_worked": time_worked,
             "comments": comment,
             "task": ticket_id
         });
-        let resp = self
-            .post_json(
-                &format!("{}/api/now/table/task_time_worked", self.instance),
-                post_body,
-            )
-            .await?;
-        if !resp.status().is_success() {
-            return Err(format!("HTTP Error while querying ServiceNow: {}", resp.status()).into());
+        if let Some(created_for) = created_for {
+            post_body["u_created_for"] = serde_json::Value::from(created_for);
         }
-        Ok(())
+        self.post_time_worked(post_body).await
     }
     pub async fn add_time_to_no_tkt(
         &self,
         category: &str,
         time_worked: &str,
         comment: &str,
-    ) -> Result<(), Box<dyn Error>> {
+        created_for: Option<&str>,
+    ) -> Result<String> {
         let time_worked = time_add_to_epoch(time_worked)?;
-        let post_body = serde_json::json!({
+        let mut post_body = serde_json::json!({
             "time_worked": time_worked,
             "comments": comment,
             "u_category": category
         });
+        if let Some(created_for) = created_for {
+            post_body["u_created_for"] = serde_json::Value::from(created_for);
+        }
+        self.post_time_worked(post_body).await
+    }
+    // Returns the sys_id of the created task_time_worked record
+    async fn post_time_worked(&self, post_body: serde_json::Value) -> Result<String> {
         let resp = self
             .post_json(
                 &format!("{}/api/now/table/task_time_worked", self.instance),
                 post_body,
             )
             .await?;
-        if !resp.status().is_success() {
-            return Err(format!("HTTP Error while querying ServiceNow: {}", resp.status()).into());
-        }
+        let resp = Error::check_status(SERVICE, resp)?;
+        let result = debug_resp_json_deserialize::<SNResult<SysIdResult>>(resp).await?;
+        Ok(result.result.sys_id)
+    }
+    pub async fn delete_time_worked(&self, sys_id: &str) -> Result<()> {
+        let resp = self
+            .delete(&format!(
+                "{}/api/now/table/task_time_worked/{}",
+                self.instance, sys_id
+            ))
+            .await?;
+        Error::check_status(SERVICE, resp)?;
         Ok(())
     }
 
     // Returns the sys_id of created or errors
-    pub async fn create_ticket(
-        &self,
-        assignment_group: &str,
-        description: &str,
-    ) -> Result<String, Box<dyn Error>> {
+    pub async fn create_ticket(&self, assignment_group: &str, description: &str) -> Result<String> {
         let ticket = TicketCreation {
             assignment_group: assignment_group.to_owned(),
             short_description: description.to_owned(),
@@ -147,40 +230,39 @@ impl ServiceNow {
             item: None,
             sla_type: Some("server_specific".to_owned()),
         };
-        let json_payload = serde_json::to_value(ticket);
-
-        if json_payload.is_err() {
-            return Err(format!("JSON error: {}", json_payload.unwrap_err()).into());
-        }
+        let json_payload = serde_json::to_value(ticket).map_err(|error| Error::Json {
+            error,
+            body: String::new(),
+        })?;
 
         let resp = self
             .post_json(
                 &format!("{}/api/now/table/sc_req_item", self.instance),
-                json_payload.unwrap(),
+                json_payload,
             )
-            .await?
-            .json::<SNResult<SysIdResult>>()
             .await?;
+        let resp = Error::check_status(SERVICE, resp)?;
+        let result = debug_resp_json_deserialize::<SNResult<SysIdResult>>(resp).await?;
+
+        Ok(result.result.sys_id)
+    }
 
-        Ok(resp.result.sys_id)
+    // Active choices of a choice list field in the order ServiceNow shows them
+    pub async fn get_choices(&self, table: &str, element: &str) -> Result<Vec<Choice>> {
+        self.get_all_pages::<Choice>(&format!(
+            "{}/api/now/table/sys_choice?sysparm_query=name={}^element={}^inactive=false^language=en^ORDERBYsequence&sysparm_fields=value,label,hint",
+            self.instance, table, element
+        ))
+        .await
     }
 
     // Searches for std chgs in ServiceNow
-    pub async fn search_std_chg(&self, name: &str) -> Result<Vec<SysIdResult>, Box<dyn Error>> {
-        let resp = self.get(&format!(
+    pub async fn search_std_chg(&self, name: &str) -> Result<Vec<SysIdResult>> {
+        self.get_all_pages::<SysIdResult>(&format!(
             "{}/api/now/table/std_change_record_producer?sysparm_query=sys_nameLIKE{}^active=true&sysparm_fields=sys_id,sys_name",
             self.instance, name
-        )).await?;
-        if !resp.status().is_success() {
-            return Err(format!("HTTP Error while querying ServiceNow: {}", resp.status()).into());
-        }
-        let result = debug_resp_json_deserialize::<SNResult<Vec<SysIdResult>>>(resp).await;
-        if result.is_err() {
-            let error_msg = format!("JSON error: {}", result.unwrap_err());
-            tracing::error!("{}", error_msg);
-            return Err(error_msg.into());
-        }
-        Ok(result.unwrap().result)
+        ))
+        .await
     }
 
     // Returns the sys_id of created CHG or errors
@@ -188,7 +270,7 @@ impl ServiceNow {
         &self,
         template_sys_id: &str,
         assignment_group: &str,
-    ) -> Result<String, Box<dyn Error>> {
+    ) -> Result<String> {
         let post_body = serde_json::json!({
             "assignment_group": assignment_group
         });
@@ -201,95 +283,229 @@ impl ServiceNow {
                 post_body,
             )
             .await?;
-        if !resp.status().is_success() {
-            return Err(format!("HTTP Error while querying ServiceNow: {}", resp.status()).into());
-        }
-        let result = debug_resp_json_deserialize::<SNResult<CHGCreation>>(resp).await;
-        if result.is_err() {
-            let error_msg = format!("JSON error: {}", result.unwrap_err());
-            tracing::error!("{}", error_msg);
-            return Err(error_msg.into());
-        }
-        Ok(result.unwrap().result.sys_id.value)
+        let resp = Error::check_status(SERVICE, resp)?;
+        let result = debug_resp_json_deserialize::<SNResult<CHGCreation>>(resp).await?;
+        Ok(result.result.sys_id.value)
     }
     pub async fn get_user_time_worked(
         &self,
         start: &str,
         end: &str,
         user: &str,
-    ) -> Result<Vec<TimeWorked>, Box<dyn Error>> {
-        let resp = self.get(&format!(
-            "{}/api/now/table/task_time_worked?sysparm_fields=task,time_in_seconds,u_category&sysparm_exclude_reference_link=true&sysparm_query=sys_created_by={}^u_created_forBETWEENjavascript:gs.dateGenerate('{}','start')@javascript:gs.dateGenerate('{}','end')",
-            self.instance,  user, start, end,
-        )).await?;
-
-        if !resp.status().is_success() {
-            return Err(format!("HTTP Error while querying ServiceNow: {}", resp.status()).into());
-        }
-        Ok(
-            debug_resp_json_deserialize::<SNResult<Vec<TimeWorked>>>(resp)
-                .await?
-                .result,
-        )
+    ) -> Result<Vec<TimeWorked>> {
+        self.get_all_pages::<TimeWorked>(&format!(
+            "{}/api/now/table/task_time_worked?sysparm_fields=task,time_in_seconds,u_category,u_created_for&sysparm_exclude_reference_link=true&sysparm_query=sys_created_by={}^u_created_forBETWEENjavascript:gs.dateGenerate('{}','start')@javascript:gs.dateGenerate('{}','end')",
+            self.instance, user, start, end,
+        ))
+        .await
     }
-    pub async fn get_tasks_cost_centers(
+    pub async fn get_tasks_by_sys_id(
         &self,
-        task_sys_id: &Vec<String>,
-    ) -> Result<Vec<CostCenter>, Box<dyn Error>> {
-        let task_sys_ids = task_sys_id.join("^ORtask=");
-        let resp = self.get(
-            &format!("{}/api/now/table/task_cost_center?sysparm_query=task={}&sysparm_display_value=all&sysparm_exclude_reference_link=true&sysparm_fields=task,cost_center", self.instance, task_sys_ids),
-        ).await?;
+        task_sys_ids: &[String],
+    ) -> Result<Vec<ShortDescNumberID>> {
+        self.get_all_pages_chunked(task_sys_ids, |sys_ids| {
+            format!(
+                "{}/api/now/table/task?sysparm_fields=sys_id,short_description,number&sysparm_query=sys_idIN{}",
+                self.instance, sys_ids
+            )
+        })
+        .await
+    }
+    pub async fn get_tasks_cost_centers(&self, task_sys_id: &[String]) -> Result<Vec<CostCenter>> {
+        self.get_all_pages_chunked(task_sys_id, |task_sys_ids| {
+            format!(
+                "{}/api/now/table/task_cost_center?sysparm_query=taskIN{}&sysparm_display_value=all&sysparm_exclude_reference_link=true&sysparm_fields=task,cost_center",
+                self.instance, task_sys_ids
+            )
+        })
+        .await
+    }
 
-        if !resp.status().is_success() {
-            return Err(format!("HTTP Error while querying ServiceNow: {}", resp.status()).into());
+    // Splits sys_ids into chunks so query strings stay short, running a few chunks at a time
+    pub async fn get_all_pages_chunked<T, F>(
+        &self,
+        sys_ids: &[String],
+        path_for_chunk: F,
+    ) -> Result<Vec<T>>
+    where
+        T: serde::de::DeserializeOwned + std::fmt::Debug,
+        F: Fn(&str) -> String,
+    {
+        // An empty IN query matches every record
+        if sys_ids.is_empty() {
+            return Ok(Vec::new());
+        }
+        let chunk_results: Vec<Result<Vec<T>>> = stream::iter(sys_ids.chunks(SYS_ID_CHUNK_SIZE))
+            .map(|chunk| {
+                let path = path_for_chunk(&chunk.join(","));
+                async move { self.get_all_pages::<T>(&path).await }
+            })
+            .buffer_unordered(CHUNK_CONCURRENCY)
+            .collect()
+            .await;
+        let mut results: Vec<T> = Vec::new();
+        for chunk_result in chunk_results {
+            results.extend(chunk_result?);
         }
-        Ok(
-            debug_resp_json_deserialize::<SNResult<Vec<CostCenter>>>(resp)
+        Ok(results)
+    }
+
+    // Follows the Link header (or sysparm_offset when it is missing) until every page is read
+    pub async fn get_all_pages<T: serde::de::DeserializeOwned + std::fmt::Debug>(
+        &self,
+        path: &str,
+    ) -> Result<Vec<T>> {
+        let mut results: Vec<T> = Vec::new();
+        let mut next_page = Some(with_page_params(path, PAGE_SIZE, 0));
+        while let Some(page) = next_page {
+            let resp = Error::check_status(SERVICE, self.get(&page).await?)?;
+            let next_link = get_next_link(resp.headers());
+            let page_results = debug_resp_json_deserialize::<SNResult<Vec<T>>>(resp)
                 .await?
-                .result,
-        )
+                .result;
+            let count = page_results.len();
+            results.extend(page_results);
+            next_page = match next_link {
+                _ if count == 0 => None,
+                Some(link) => Some(link),
+                None if count == PAGE_SIZE => {
+                    Some(with_page_params(path, PAGE_SIZE, results.len()))
+                }
+                None => None,
+            };
+        }
+        Ok(results)
+    }
+}
+
+// Base url for an instance name (liberty), a host (sn.example.edu:8443) or a full url with a
+// path prefix (https://example.edu/servicenow). Hosts without a scheme use https
+pub fn instance_url(instance: &str) -> String {
+    let instance = instance.trim().trim_end_matches('/');
+    if instance.contains("://") {
+        instance.to_owned()
+    } else if instance.contains(['.', ':', '/']) {
+        format!("https://{}", instance)
+    } else {
+        format!("https://{}.service-now.com", instance)
     }
 }
 
-pub fn time_add_to_epoch(time: &str) -> Result<String, Box<dyn Error>> {
-    let time_regex = Regex::new(r"^(?:(\d+)h)?(?:(\d+)m)?$").unwrap();
-    let captures = time_regex
+// Link to a record in the ServiceNow UI, e.g. table task gives {instance}/task.do?sys_id=
+pub fn record_link(instance: &str, table: &str, sys_id: &str) -> String {
+    format!("{}/{}.do?sys_id={}", instance_url(instance), table, sys_id)
+}
+
+// Number of records requested per page from the Table API
+pub const PAGE_SIZE: usize = 1000;
+// Number of sys_ids sent in a single IN query
+pub const SYS_ID_CHUNK_SIZE: usize = 100;
+// Number of chunked queries sent to ServiceNow at the same time
+pub const CHUNK_CONCURRENCY: usize = 4;
+
+pub fn with_page_params(path: &str, limit: usize, offset: usize) -> String {
+    let separator = if path.contains('?') { '&' } else { '?' };
+    format!(
+        "{}{}sysparm_limit={}&sysparm_offset={}",
+        path, separator, limit, offset
+    )
+}
+
+// Returns the rel="next" url from a Link header
+pub fn get_next_link(headers: &reqwest::header::HeaderMap) -> Option<String> {
+    let link = headers.get(reqwest::header::LINK)?.to_str().ok()?;
+    link.split(',').find_map(|part| {
+        let (url, params) = part.split_once(';')?;
+        params
+            .split(';')
+            .any(|p| p.trim().replace(' ', "") == "rel=\"next\"")
+            .then(|| {
+                url.trim()
+                    .trim_start_matches('<')
+                    .trim_end_matches('>')
+                    .to_string()
+            })
+    })
+}
+
+// Longest duration of a single entry, multi-day entries are allowed up to a week
+pub const MAX_TIME_WORKED_SECONDS: i64 = 7 * 24 * 3600;
+
+pub const TIME_FORMAT_HELP: &str =
+    "Valid formats are 1h30m, 2h 15m, 1h30, 1.5h, 90m, 45s, 1h2m3s, 1:30 and 1:30:15";
+
+// Parses a duration such as "1h30m", "1.5h", "90m", "1h30" or "1:30" into seconds
+pub fn parse_time_worked(time: &str) -> Result<i64> {
+    let time = time.trim();
+    let unit_regex = Regex::new(
+        r"^(?:(\d+(?:\.\d+)?)\s*h)?\s*(?:(\d+(?:\.\d+)?)\s*m)?\s*(?:(\d+(?:\.\d+)?)\s*s)?$",
+    )
+    .unwrap();
+    let trailing_minutes_regex = Regex::new(r"^(\d+)\s*h\s*(\d+)$").unwrap();
+    let colon_regex = Regex::new(r"^(\d+):(\d{1,2})(?::(\d{1,2}))?$").unwrap();
+
+    let (hours, minutes, seconds): (Option<f64>, Option<f64>, Option<f64>);
+    if let Some(captures) = colon_regex
         .captures(time)
-        .ok_or("Invalid time format. Please use optional hh and/or mm")?;
+        .or(trailing_minutes_regex.captures(time))
+    {
+        let parse = |i: usize| captures.get(i).map(|c| c.as_str().parse::<f64>().unwrap());
+        (hours, minutes, seconds) = (parse(1), parse(2), parse(3));
+    } else if let Some(captures) = unit_regex.captures(time).filter(|_| !time.is_empty()) {
+        let parse = |i: usize| captures.get(i).map(|c| c.as_str().parse::<f64>().unwrap());
+        (hours, minutes, seconds) = (parse(1), parse(2), parse(3));
+    } else {
+        return Err(Error::Validation(format!(
+            "Invalid time format \"{}\". {}",
+            time, TIME_FORMAT_HELP
+        )));
+    }
 
-    let hours: u32 = captures
-        .get(1)
-        .map_or(Ok(0), |h| h.as_str().parse())
-        .unwrap();
-    let minutes: u32 = captures
-        .get(2)
-        .map_or(Ok(0), |m| m.as_str().parse())
-        .unwrap();
-    if hours > 19 || minutes > 59 {
-        return Err(
-            "Invalid time format. Values must be below 20 for hours, 60 for minutes".into(),
-        );
+    // Smaller units must fit within the larger unit they follow (1h90m is a typo, 90m is not)
+    if (hours.is_some() && minutes.is_some_and(|m| m >= 60.0))
+        || ((hours.is_some() || minutes.is_some()) && seconds.is_some_and(|s| s >= 60.0))
+    {
+        return Err(Error::Validation(format!(
+            "Invalid time format \"{}\". Minutes and seconds must be below 60 when combined with a larger unit",
+            time
+        )));
+    }
+    let total = hours.unwrap_or_default() * 3600.0
+        + minutes.unwrap_or_default() * 60.0
+        + seconds.unwrap_or_default();
+    if total > MAX_TIME_WORKED_SECONDS as f64 {
+        return Err(Error::Validation(format!(
+            "Time worked \"{}\" is longer than the maximum of {}h per entry",
+            time,
+            MAX_TIME_WORKED_SECONDS / 3600
+        )));
     }
-    let epoch_time = (hours * 3600 + minutes * 60) as i64;
-    if epoch_time == 0 {
-        return Err("Time worked must be greater than 0 minutes".into());
+    let total = total.round() as i64;
+    if total == 0 {
+        return Err(Error::Validation(
+            "Time worked must be greater than 0 minutes".to_string(),
+        ));
     }
-    let formatted_time = Utc
-        .timestamp_opt(epoch_time, 0)
-        .unwrap()
+    Ok(total)
+}
+
+// ServiceNow durations are date times offset from the epoch, so days roll over into the date
+pub fn seconds_to_epoch(seconds: i64) -> String {
+    (DateTime::UNIX_EPOCH + Duration::seconds(seconds))
         .format("%Y-%m-%d+%H:%M:%S")
-        .to_string();
-    Ok(formatted_time)
+        .to_string()
+}
+
+pub fn time_add_to_epoch(time: &str) -> Result<String> {
+    Ok(seconds_to_epoch(parse_time_worked(time)?))
 }
 
 pub async fn debug_resp_json_deserialize<T: serde::de::DeserializeOwned + std::fmt::Debug>(
     resp: reqwest::Response,
-) -> Result<T, Box<dyn Error>> {
+) -> Result<T> {
     let text = resp.text().await?;
-    let json: Result<T, serde_json::Error> = serde_json::from_str(&text);
-    if json.is_err() {
-        return Err(format!("JSON error: {} \n{}", json.unwrap_err(), text).into());
-    }
-    Ok(json.unwrap())
+    serde_json::from_str(&text).map_err(|error| {
+        tracing::error!("JSON error: {}", error);
+        Error::Json { error, body: text }
+    })
 }
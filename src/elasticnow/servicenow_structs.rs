@@ -1,4 +1,5 @@
 use crate::elasticnow::elasticnow::ChooseOptions;
+use crate::elasticnow::error::{Error, Result};
 use crate::elasticnow::servicenow;
 use serde::{Deserialize, Serialize};
 
@@ -17,7 +18,7 @@ pub enum ServiceNowResultResponse {
     TimeWorked(TimeWorked),
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone)]
 pub struct SysIdResult {
     pub sys_id: String,
     pub sys_name: Option<String>,
@@ -74,18 +75,11 @@ impl LinkAndValue {
     pub async fn get_link<T: serde::de::DeserializeOwned + std::fmt::Debug>(
         &self,
         sn_client: &servicenow::ServiceNow,
-    ) -> Result<T, Box<dyn std::error::Error>> {
-        let resp = sn_client.get(&self.link).await?;
-        if !resp.status().is_success() {
-            return Err(format!("HTTP Error while querying ServiceNow: {}", resp.status()).into());
-        }
-        let result = servicenow::debug_resp_json_deserialize::<SNResult<T>>(resp).await;
-        if result.is_err() {
-            let error_msg = format!("JSON error: {}", result.unwrap_err());
-            tracing::error!("{}", error_msg);
-            return Err(error_msg.into());
-        }
-        Ok(result.unwrap().result)
+    ) -> Result<T> {
+        let resp = Error::check_status("ServiceNow", sn_client.get(&self.link).await?)?;
+        Ok(servicenow::debug_resp_json_deserialize::<SNResult<T>>(resp)
+            .await?
+            .result)
     }
 }
 
@@ -96,26 +90,45 @@ pub enum TimeWorkedTask {
     EmptyString(String),
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct TimeWorked {
     pub time_in_seconds: String,
     pub task: String,
     #[serde(rename = "u_category")]
     pub category: String,
+    #[serde(rename = "u_created_for", default)]
+    pub created_for: String,
 }
 
 impl TimeWorked {
-    pub fn get_nice_name_category(&self) -> String {
-        match self.category.as_str() {
-            "certs_prodev_training" => "Training".to_string(),
-            "clerical" => "Clerical".to_string(),
-            "univ_events" => "University Events".to_string(),
-            _ => self.category.clone(),
+    // Label of the category in categories, time worked on tickets has no category
+    pub fn get_nice_name_category(&self, categories: &[Choice]) -> String {
+        if self.category.is_empty() {
+            return "Ticket work".to_string();
         }
+        categories
+            .iter()
+            .find(|c| c.value == self.category)
+            .map(|c| c.label.clone())
+            .unwrap_or(self.category.clone())
+    }
+    // The day (YYYY-MM-DD) the time was tracked for
+    pub fn get_day(&self) -> String {
+        self.created_for.chars().take(10).collect()
     }
 }
-#[derive(Debug, Serialize, Deserialize)]
 
+/// Entry of a choice list from sys_choice, e.g. a u_category of task_time_worked
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Choice {
+    pub value: String,
+    pub label: String,
+    /// Longer description shown next to the label when choosing
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub hint: String,
+}
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ShortDescNumberID {
     pub short_description: String,
     pub number: String,
@@ -129,9 +142,15 @@ impl ChooseOptions<ShortDescNumberID> for ShortDescNumberID {
     fn get_number(&self) -> String {
         self.number.clone()
     }
+    fn get_short_description(&self) -> String {
+        self.short_description.clone()
+    }
     fn get_id(&self) -> String {
         self.sys_id.clone()
     }
+    fn get_preview(&self) -> Vec<String> {
+        vec![self.short_description.clone()]
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -1,18 +1,64 @@
 use ansi_term::Colour;
-use elasticnow::cli::{self, args, config};
+use elasticnow::cli::credentials::CredentialStore;
+use elasticnow::cli::picker::PickerItem;
+use elasticnow::cli::{
+    self, args, categories, config, create, history, import, queue, report, timer, timetrack,
+};
+use elasticnow::elasticnow::backend::{TicketBackend, TicketSearch, TimeWorkedBackend};
 use elasticnow::elasticnow::elasticnow::ChooseOptions;
 use elasticnow::elasticnow::elasticnow::{ElasticNow, SearchResult};
-use elasticnow::elasticnow::servicenow::ServiceNow;
-use elasticnow::elasticnow::servicenow_structs::TimeWorked;
+use elasticnow::elasticnow::error::Error;
+use elasticnow::elasticnow::oauth::OAuth;
+use elasticnow::elasticnow::servicenow::{self, ServiceNow};
+use elasticnow::elasticnow::servicenow_structs::{Choice, ShortDescNumberID};
 use open::that;
-use std::collections::HashMap;
+use std::io::IsTerminal;
 use std::net::TcpListener;
 use tiny_http::{Response, Server};
 use tracing_subscriber::{fmt, prelude::*, EnvFilter};
 
+struct TimetrackOptions {
+    new: bool,
+    comment: String,
+    time_worked: String,
+    search: Option<String>,
+    bin: Option<String>,
+    no_tkt: bool,
+    // Category for no_tkt, prompted for when unset
+    category: Option<String>,
+    // Ticket number or sys_id to track the time on without searching
+    ticket: Option<String>,
+    sys_id: Option<String>,
+    // Use the first search result instead of prompting
+    pick_first: bool,
+    // Choose from the history instead of searching
+    recent: bool,
+    all: bool,
+    // Day the time is tracked for (YYYY-MM-DD), ServiceNow defaults to today
+    date: Option<String>,
+}
+
+struct SetupOptions {
+    id: String,
+    instance: String,
+    sn_instance: String,
+    sn_username: String,
+    sn_password: Option<String>,
+    bin: Option<String>,
+    credential_store: Option<CredentialStore>,
+    password_command: Option<String>,
+    auth: Option<config::AuthMethod>,
+    oauth_client_id: Option<String>,
+    oauth_client_secret: Option<String>,
+}
+
 struct ValueOption {
     value: String,
+    // Label in the picker
     display_value: String,
+    number: String,
+    short_description: String,
+    preview: Vec<String>,
 }
 
 #[tokio::main]
@@ -28,6 +74,7 @@ async fn main() {
         .with(EnvFilter::from_env("ELASTICNOW_LOG_LEVEL"))
         .init();
     let args = cli::args::get_args();
+    config::select_profile(args.profile.clone());
     match args.cmd {
         Some(cli::args::Commands::Timetrack {
             new,
@@ -36,9 +83,54 @@ async fn main() {
             search,
             bin,
             no_tkt,
+            category,
+            ticket,
+            sys_id,
+            pick_first,
+            recent,
+            split,
             all,
+            date,
+            yesterday,
         }) => {
-            run_timetrack(new, comment, time_worked, search, bin, no_tkt, all).await;
+            let date = if yesterday {
+                Some(args::get_yesterday())
+            } else {
+                date
+            };
+            if let Some(date) = &date {
+                if let Err(e) = args::range_format_validate(date) {
+                    tracing::error!("Invalid date format: {:?}", e);
+                    std::process::exit(1);
+                }
+            }
+            if let Some(split) = split {
+                run_split(split, time_worked, comment, date).await;
+                return;
+            }
+            run_timetrack(TimetrackOptions {
+                new,
+                comment,
+                time_worked,
+                search,
+                bin,
+                no_tkt,
+                category,
+                ticket,
+                sys_id,
+                pick_first,
+                recent,
+                all,
+                date,
+            })
+            .await;
+        }
+
+        Some(cli::args::Commands::Sync) => {
+            run_sync().await;
+        }
+        Some(cli::args::Commands::Timer { cmd }) => {
+            run_timer(cmd).await;
         }
 
         Some(cli::args::Commands::StdChg {
@@ -48,6 +140,20 @@ async fn main() {
         }) => {
             run_stdchg(search.unwrap_or_default(), bin, template_id).await;
         }
+        Some(cli::args::Commands::Import {
+            file,
+            output,
+            dry_run,
+            yes,
+        }) => {
+            run_import(file, output, dry_run, yes).await;
+        }
+        Some(cli::args::Commands::Fav { cmd }) => {
+            run_fav(cmd).await;
+        }
+        Some(cli::args::Commands::Config { cmd }) => {
+            run_config(cmd).await;
+        }
         Some(cli::args::Commands::Setup {
             id,
             instance,
@@ -55,8 +161,26 @@ async fn main() {
             sn_username,
             sn_password,
             bin,
+            credential_store,
+            password_command,
+            auth,
+            oauth_client_id,
+            oauth_client_secret,
         }) => {
-            run_setup(id, instance, sn_instance, sn_username, sn_password, bin).await;
+            run_setup(SetupOptions {
+                id,
+                instance,
+                sn_instance,
+                sn_username,
+                sn_password,
+                bin,
+                credential_store,
+                password_command,
+                auth,
+                oauth_client_id,
+                oauth_client_secret,
+            })
+            .await;
         }
 
         Some(cli::args::Commands::Report {
@@ -65,11 +189,18 @@ async fn main() {
             until,
             top,
             today,
+            format,
+            daily,
+            mut by,
+            target,
         }) => {
             if today {
                 since = Some(args::get_today());
             }
-            run_report(user, since, until, top).await;
+            if daily {
+                by = args::ReportBy::Day;
+            }
+            run_report(user, since, until, top, format, by, target).await;
         }
         _ => {
             std::process::exit(1);
@@ -77,37 +208,74 @@ async fn main() {
     }
 }
 
-async fn run_timetrack(
-    new: bool,
-    comment: String,
-    time_worked: String,
-    search: Option<String>,
-    bin: Option<String>,
-    no_tkt: bool,
-    all: bool,
-) {
+async fn run_timetrack(opts: TimetrackOptions) {
+    let TimetrackOptions {
+        new,
+        comment,
+        time_worked,
+        search,
+        bin,
+        no_tkt,
+        category,
+        ticket,
+        sys_id: ticket_sys_id,
+        pick_first,
+        recent,
+        all,
+        date,
+    } = opts;
     let (mut config, sn_client) = check_config();
     tracing::debug!("New: {:?}", new);
     tracing::debug!("Comment: {:?}", comment);
     tracing::debug!("Time Worked: {:?}", time_worked);
     tracing::debug!("Search: {:?}", search);
     tracing::debug!("Bin: {:?}", bin);
+    tracing::debug!("Date: {:?}", date);
 
     let tkt_bin = bin.unwrap_or(config.bin.clone());
+    let mut history = history::History::from_toml_file().unwrap_or_else(|e| {
+        tracing::warn!("Unable to read ticket history: {}", e);
+        history::History::default()
+    });
+    // Ticket the time is tracked on, for the history
+    let mut chosen: Option<ShortDescNumberID> = None;
     let mut sys_id: String = "".to_string();
-    let resp: Result<(), Box<dyn std::error::Error>>;
+    let target: timetrack::TimeTarget;
     if no_tkt {
-        let category = cli::args::choose_category();
-        resp = sn_client
-            .add_time_to_no_tkt(&category, &time_worked, &comment)
-            .await;
+        let categories = load_categories(&sn_client, &config).await;
+        let category = match category {
+            Some(name) => timetrack::resolve_category(&categories, &name).unwrap_or_else(|e| {
+                tracing::error!("{}", e);
+                std::process::exit(1);
+            }),
+            None => cli::args::choose_category(&categories),
+        };
+        target = timetrack::TimeTarget::Category(category);
     } else {
-        if new {
+        // Checked before posting, ServiceNow would accept time on a task that does not exist
+        let ticket_ref = match (ticket_sys_id, ticket) {
+            (Some(ticket_sys_id), _) => Some(timetrack::TicketRef::SysId(ticket_sys_id)),
+            (None, Some(number)) => Some(timetrack::TicketRef::Number(number)),
+            (None, None) => None,
+        };
+        if let Some(ticket_ref) = ticket_ref {
+            match timetrack::find_ticket(&sn_client, &ticket_ref).await {
+                Ok(found) => {
+                    sys_id = found.get_id();
+                    chosen = Some(found);
+                }
+                Err(e) => {
+                    tracing::error!("Unable to find ticket {}: {}", ticket_ref, e);
+                    std::process::exit(if e.is_transient() { 2 } else { 1 });
+                }
+            }
+        } else if new {
             sys_id = new_ticket(&sn_client, &config).await;
         } else {
-            let tkt_options_string: Vec<String>;
-            let tkt_options: Vec<ValueOption>;
-            if all {
+            let mut tkt_options: Vec<ValueOption>;
+            if recent {
+                tkt_options = Vec::new();
+            } else if all {
                 let tkt_options_res = sn_client.get_all_tickets_in_bin(&tkt_bin).await;
                 if tkt_options_res.is_err() {
                     tracing::error!("Unable to get tickets: {:?}", tkt_options_res.err());
@@ -115,14 +283,29 @@ async fn run_timetrack(
                 }
                 let tkt_options_generic = tkt_options_res.unwrap();
                 tkt_options = generic_options_to_value_option(&tkt_options_generic);
-                tkt_options_string = search_results_to_string(&tkt_options_generic);
             } else {
-                let mut es_now_client = ElasticNow::new(&config.id, &config.instance);
-                if es_now_client.check_auth().await.is_err() {
+                let mut es_now_client =
+                    ElasticNow::new(&config.id, &config.instance).with_retry(config.retry.clone());
+                if let Err(e) = es_now_client.check_auth().await {
+                    // Logging in again will not help when ElasticNow cannot be reached
+                    if e.is_transient() {
+                        tracing::error!("Unable to reach ElasticNow: {}", e);
+                        std::process::exit(2);
+                    }
+                    // Logging in opens a browser and waits for it, which a script cannot answer
+                    if pick_first || !std::io::stdin().is_terminal() {
+                        tracing::error!(
+                            "The ElasticNow session has expired ({}). Log in again by running {} in a terminal",
+                            e,
+                            Colour::Green.bold().paint("elasticnow timetrack")
+                        );
+                        std::process::exit(1);
+                    }
                     tracing::error!("Unable to authenticate to ElasticNow trying to log in");
                     let _cookie = get_cookie_from_browser(&config.instance);
                     config.set_new_id(&_cookie);
-                    es_now_client = ElasticNow::new(&config.id, &config.instance);
+                    es_now_client = ElasticNow::new(&config.id, &config.instance)
+                        .with_retry(config.retry.clone());
                     let err = es_now_client.check_auth().await;
                     if err.is_err() {
                         tracing::error!("login attempt failed");
@@ -132,9 +315,38 @@ async fn run_timetrack(
                 let keywords = search.clone().unwrap_or("".to_string());
                 let tkt_options_generic = search_tickets(es_now_client, &tkt_bin, &keywords).await;
                 tkt_options = generic_options_to_value_option(&tkt_options_generic);
-                tkt_options_string = search_results_to_string(&tkt_options_generic);
             }
-            let item = cli::args::choose_options(tkt_options_string);
+            // Favourites and recent tickets go first, --pick-first still takes the best match
+            if !pick_first {
+                let history_options = history_to_value_option(&history.picker_entries());
+                tkt_options.retain(|o| !history_options.iter().any(|h| h.value == o.value));
+                tkt_options.splice(0..0, history_options);
+            }
+            if recent && tkt_options.is_empty() {
+                tracing::error!(
+                    "No favourite or recent tickets yet, use {} or {}",
+                    Colour::Green.bold().paint("--search"),
+                    Colour::Green.bold().paint("elasticnow fav add")
+                );
+                std::process::exit(1);
+            }
+            let item = if pick_first {
+                let Some(first) = tkt_options.first() else {
+                    tracing::error!("No tickets found in {}", tkt_bin);
+                    std::process::exit(1);
+                };
+                first.display_value.clone()
+            } else {
+                cli::args::choose_options(
+                    tkt_options
+                        .iter()
+                        .map(|o| PickerItem {
+                            label: o.display_value.clone(),
+                            preview: o.preview.clone(),
+                        })
+                        .collect(),
+                )
+            };
             tracing::debug!("Selected item: {}", &item);
             match &*item {
                 "Cancel" => {
@@ -149,30 +361,318 @@ async fn run_timetrack(
                         tracing::error!("Unexpected error on input");
                         std::process::exit(2);
                     }
-                    sys_id = tkt.unwrap().value;
+                    let tkt = tkt.unwrap();
+                    sys_id = tkt.value.clone();
+                    chosen = Some(ShortDescNumberID {
+                        short_description: tkt.short_description,
+                        number: tkt.number,
+                        sys_id: tkt.value,
+                    });
                 }
             }
         }
         tracing::debug!("Adding sys_id: {}", sys_id);
-
-        resp = sn_client
-            .add_time_to_ticket(&sys_id, &time_worked, &comment)
-            .await;
+        target = timetrack::TimeTarget::Ticket(sys_id.clone());
     }
 
-    if resp.is_err() {
-        tracing::error!("Unable to add time to ticket: {:?}", resp.err());
-
-        std::process::exit(2);
+    let entry = timetrack::TimeEntry {
+        target,
+        time_worked: time_worked.clone(),
+        comment,
+        date,
+    };
+    if let Err(e) = timetrack::track_time(&sn_client, &entry).await {
+        // Only requests ServiceNow never processed are queued, replaying any other could track the
+        // time twice
+        if !e.is_unsent() {
+            tracing::error!("Unable to add time to ticket: {:?}", e);
+            if e.is_transient() {
+                log_maybe_tracked();
+            }
+            std::process::exit(2);
+        }
+        let queued = queue::QueuedEntry::from_time_entry(
+            &entry,
+            entry.date.clone().unwrap_or(args::get_today()),
+            Some(e.to_string()),
+        );
+        if let Err(queue_err) = queue::Queue::push(queued) {
+            tracing::error!("Unable to add time to ticket: {:?}", e);
+            tracing::error!("Unable to queue time entry: {:?}", queue_err);
+            std::process::exit(2);
+        }
+        tracing::warn!(
+            "ServiceNow is unreachable ({}). Queued {} of time, run {} to retry",
+            e,
+            ansi_term::Colour::Green.paint(time_worked),
+            Colour::Green.bold().paint("elasticnow sync")
+        );
+        return;
     }
     let time_worked_msg = ansi_term::Colour::Green.paint(time_worked);
     tracing::info!("Tracking {} of time", time_worked_msg);
     if !no_tkt {
-        let ticket_url = ansi_term::Colour::Blue.paint(format!(
-            "https://{}.service-now.com/task.do?sys_id={}",
-            &config.sn_instance, sys_id
-        ));
+        let ticket_url = ansi_term::Colour::Blue.paint(sn_client.record_link("task", &sys_id));
         println!("Link to ticket: {}", ticket_url);
+        if timetrack::record_ticket(&sn_client, &mut history, &sys_id, chosen).await {
+            save_history(&history);
+        }
+    }
+}
+
+// Tracks the time across several tickets and categories, all of it or none of it
+async fn run_split(split: String, time_worked: String, comment: String, date: Option<String>) {
+    let (config, sn_client) = check_config();
+    let categories = load_categories(&sn_client, &config).await;
+    let resolved = timetrack::resolve_split(
+        &sn_client,
+        &categories,
+        &split,
+        &time_worked,
+        &comment,
+        date.as_deref(),
+    )
+    .await
+    .unwrap_or_else(|e| {
+        tracing::error!("Invalid split: {}", e);
+        std::process::exit(if e.is_transient() { 2 } else { 1 });
+    });
+    let (entries, targets): (Vec<_>, Vec<_>) = resolved.into_iter().unzip();
+    let labels: Vec<String> = targets.iter().map(|t| t.label.clone()).collect();
+
+    if let Err(failure) = timetrack::track_split(&sn_client, &entries).await {
+        tracing::error!(
+            "Unable to add time to {}: {}",
+            labels[failure.index],
+            failure.error
+        );
+        // The failed post may have created a record whose sys_id was never returned
+        let uncertain = failure.error.is_transient() && !failure.error.is_unsent();
+        if !failure.not_rolled_back.is_empty() {
+            tracing::error!("Unable to delete these records, remove them before retrying:");
+            for sys_id in &failure.not_rolled_back {
+                println!(
+                    "{}",
+                    Colour::Blue.paint(sn_client.record_link("task_time_worked", sys_id))
+                );
+            }
+        } else if uncertain {
+            tracing::error!(
+                "The records already created were deleted, but the time for {} may still be tracked",
+                labels[failure.index]
+            );
+        } else {
+            tracing::error!("Nothing was tracked, the records already created were deleted");
+        }
+        if uncertain {
+            log_maybe_tracked();
+        }
+        std::process::exit(2);
+    }
+    tracing::info!(
+        "Tracking {} of time across {} entries",
+        Colour::Green.paint(&time_worked),
+        entries.len()
+    );
+    for (entry, label) in entries.iter().zip(&labels) {
+        println!(
+            "{}  {}",
+            Colour::Green.paint(format!("{:>8}", entry.time_worked)),
+            label
+        );
+    }
+
+    let tickets: Vec<ShortDescNumberID> = targets.into_iter().filter_map(|t| t.ticket).collect();
+    if tickets.is_empty() {
+        return;
+    }
+    let mut history = history::History::from_toml_file().unwrap_or_else(|e| {
+        tracing::warn!("Unable to read ticket history: {}", e);
+        history::History::default()
+    });
+    for ticket in &tickets {
+        history.record(&ticket.sys_id, &ticket.number, &ticket.short_description);
+    }
+    save_history(&history);
+}
+
+// The time is already tracked, so a history that cannot be saved is only logged
+fn save_history(history: &history::History) {
+    if let Err(e) = history.to_toml_file() {
+        tracing::warn!("Unable to save ticket history: {}", e);
+    }
+}
+
+async fn run_sync() {
+    let (_config, sn_client) = check_config();
+    let queue = queue::Queue::from_toml_file();
+    if queue.is_err() {
+        tracing::error!("Unable to read queue file: {:?}", queue.err());
+        std::process::exit(2);
+    }
+    let mut queue = queue.unwrap();
+    if queue.entries.is_empty() {
+        tracing::info!("No queued time entries");
+        std::process::exit(0);
+    }
+    let (synced, failed, uncertain) =
+        timetrack::sync_entries(&sn_client, queue.entries.drain(..).collect()).await;
+    for entry in synced {
+        println!(
+            "{} {} on {} for {}",
+            ansi_term::Colour::Green.paint("Synced"),
+            entry.time_worked,
+            entry.date,
+            entry.get_target()
+        );
+    }
+    for entry in &failed {
+        println!(
+            "{} {} on {} for {}: {}",
+            ansi_term::Colour::Red.paint("Failed"),
+            entry.time_worked,
+            entry.date,
+            entry.get_target(),
+            entry.last_error.clone().unwrap_or_default()
+        );
+    }
+    for entry in &uncertain {
+        println!(
+            "{} {} on {} for {}: {}",
+            ansi_term::Colour::Yellow.paint("Unknown"),
+            entry.time_worked,
+            entry.date,
+            entry.get_target(),
+            entry.last_error.clone().unwrap_or_default()
+        );
+    }
+    let failed_count = failed.len();
+    queue.entries = failed;
+    if let Err(e) = queue.to_toml_file() {
+        tracing::error!("Unable to save queue file: {:?}", e);
+        std::process::exit(2);
+    }
+    if !uncertain.is_empty() {
+        tracing::error!(
+            "{} time entries were removed from the queue",
+            uncertain.len()
+        );
+        log_maybe_tracked();
+    }
+    if failed_count > 0 {
+        tracing::error!("{} time entries are still queued", failed_count);
+    }
+    if failed_count > 0 || !uncertain.is_empty() {
+        std::process::exit(2);
+    }
+    std::process::exit(0);
+}
+
+// After a timeout or 5xx ServiceNow may have created the record before failing
+fn log_maybe_tracked() {
+    tracing::error!(
+        "ServiceNow may have tracked the time anyway, check {} before tracking it again",
+        Colour::Green.bold().paint("elasticnow report")
+    );
+}
+
+async fn run_timer(cmd: cli::args::TimerCommands) {
+    let timer = timer::Timer::from_toml_file();
+    if timer.is_err() {
+        tracing::error!("Unable to read timer file: {:?}", timer.err());
+        std::process::exit(2);
+    }
+    let timer = timer.unwrap();
+    match cmd {
+        cli::args::TimerCommands::Start { comment, force } => {
+            if let Some(running) = &timer {
+                if !force {
+                    tracing::error!(
+                        "A timer has been running since {}. Use {} to replace it.",
+                        running.started_at_string(),
+                        Colour::Green.bold().paint("--force")
+                    );
+                    std::process::exit(1);
+                }
+            }
+            let new_timer = timer::Timer::start(comment);
+            if let Err(e) = new_timer.to_toml_file() {
+                tracing::error!("Unable to save timer: {:?}", e);
+                std::process::exit(2);
+            }
+            tracing::info!("Timer started at {}", new_timer.started_at_string());
+        }
+        cli::args::TimerCommands::Stop {
+            new,
+            all,
+            comment,
+            search,
+            bin,
+            no_tkt,
+            category,
+            ticket,
+            sys_id,
+            pick_first,
+            recent,
+        } => {
+            let Some(running) = timer else {
+                tracing::error!("No timer is running");
+                std::process::exit(1);
+            };
+            let Some(comment) = comment.or(running.comment.clone()) else {
+                tracing::error!(
+                    "No comment was given on start. Please provide one with {}",
+                    Colour::Green.bold().paint("--comment")
+                );
+                std::process::exit(1);
+            };
+            let time_worked = timer::seconds_to_time_worked(running.elapsed_seconds());
+            run_timetrack(TimetrackOptions {
+                new,
+                comment,
+                time_worked,
+                search,
+                bin,
+                no_tkt,
+                category,
+                ticket,
+                sys_id,
+                pick_first,
+                recent,
+                all,
+                date: None,
+            })
+            .await;
+            if let Err(e) = timer::Timer::remove_file() {
+                tracing::error!("Unable to remove timer file: {:?}", e);
+                std::process::exit(2);
+            }
+        }
+        cli::args::TimerCommands::Status => match timer {
+            Some(running) => {
+                println!(
+                    "Timer running since {} ({})",
+                    running.started_at_string(),
+                    ansi_term::Colour::Green
+                        .paint(timer::seconds_to_time_worked(running.elapsed_seconds()))
+                );
+                if let Some(comment) = running.comment {
+                    println!("Comment: {}", comment);
+                }
+            }
+            None => println!("No timer is running"),
+        },
+        cli::args::TimerCommands::Cancel => {
+            if timer.is_none() {
+                tracing::error!("No timer is running");
+                std::process::exit(1);
+            }
+            if let Err(e) = timer::Timer::remove_file() {
+                tracing::error!("Unable to remove timer file: {:?}", e);
+                std::process::exit(2);
+            }
+            tracing::info!("Timer discarded");
+        }
     }
     std::process::exit(0);
 }
@@ -182,94 +682,422 @@ async fn run_report(
     since: Option<String>,
     until: Option<String>,
     top: Option<usize>,
+    format: args::ReportFormat,
+    by: args::ReportBy,
+    target: Option<f64>,
 ) {
     let (config, sn_client) = check_config();
     let user = user.unwrap_or(config.sn_username.clone());
     let since = since.unwrap_or(args::get_week_start());
     let until = until.unwrap_or(args::get_today());
-    for date in vec![&since, &until] {
+    for date in [&since, &until] {
         let date_validate = args::range_format_validate(date);
         if date_validate.is_err() {
             tracing::error!("Invalid date format: {:?}", date_validate.err());
             std::process::exit(1);
         }
     }
-    let tasks = sn_client.get_user_time_worked(&since, &until, &user).await;
-    if tasks.is_err() {
-        tracing::error!("Unable to get time worked: {:?}", tasks.err());
-        std::process::exit(1);
-    }
-    let mut task_cat_time: HashMap<String, i64> = HashMap::new();
-    let tasks = tasks.unwrap();
-    let total = get_total(&tasks);
-    let mut tasks_ids: HashMap<String, i64> = HashMap::new();
-    for time_work in tasks {
-        match time_work.task.as_ref() {
-            "" => {
-                let time_in_seconds: i64 = time_work.time_in_seconds.parse().unwrap_or_default();
-                *task_cat_time
-                    .entry(time_work.get_nice_name_category())
-                    .or_insert(0) += time_in_seconds;
-            }
-            _ => {
-                let time_in_seconds: i64 = time_work.time_in_seconds.parse().unwrap_or_default();
-                *tasks_ids.entry(time_work.task).or_insert(0) += time_in_seconds;
-            }
-        }
-    }
-    let keys = tasks_ids.keys().cloned().collect::<Vec<String>>();
-    let cost_centers = sn_client.get_tasks_cost_centers(&keys).await;
-    if cost_centers.is_err() {
-        tracing::error!("Unable to get cost centers: {:?}", cost_centers.err());
+    let categories = load_categories(&sn_client, &config).await;
+    let summary =
+        report::summarize_time_worked(&sn_client, &user, &since, &until, by, &categories).await;
+    if summary.is_err() {
+        tracing::error!("Unable to get time worked: {:?}", summary.err());
         std::process::exit(1);
     }
-    let cost_centers = cost_centers.unwrap();
-    for cost_center in cost_centers {
-        let time = tasks_ids.get(&cost_center.task.value).unwrap_or(&0);
-        *task_cat_time
-            .entry(cost_center.cost_center.display_value)
-            .or_insert(0) += time;
+    let summary = summary.unwrap();
+    if by == args::ReportBy::Day {
+        let target = target
+            .or(config.daily_target)
+            .unwrap_or(args::DEFAULT_DAILY_TARGET_HOURS);
+        args::print_daily_time_worked(summary.time_worked, &since, &until, target, format);
+        std::process::exit(0);
     }
-    args::pretty_print_time_worked(task_cat_time, top.unwrap_or(10), total);
+    args::print_time_worked(
+        summary.time_worked,
+        top.unwrap_or(10),
+        summary.total,
+        format,
+    );
     std::process::exit(0);
 }
-async fn run_setup(
-    id: String,
-    instance: String,
-    sn_instance: String,
-    sn_username: String,
-    sn_password: String,
-    bin: Option<String>,
-) {
+async fn run_setup(opts: SetupOptions) {
+    let SetupOptions {
+        id,
+        instance,
+        sn_instance,
+        sn_username,
+        sn_password,
+        bin,
+        credential_store,
+        password_command,
+        auth,
+        oauth_client_id,
+        oauth_client_secret,
+    } = opts;
     cli::config::make_dir_if_none();
+    let credential_store = match credential_store {
+        Some(credential_store) => credential_store,
+        None if console::Term::stdout().is_term() => args::choose_credential_store(),
+        None => CredentialStore::Config,
+    };
+    // Settings setup does not ask for (daily_target, retry) are kept when updating a profile, and
+    // so are auth and the OAuth application unless new ones are given
+    let profile = config::get_profile_name();
+    let existing = config::ConfigFile::from_toml_file()
+        .and_then(|file| file.get_profile(&profile))
+        .unwrap_or_default();
+    let auth = auth.unwrap_or(existing.auth);
+    let oauth = match (oauth_client_id, existing.oauth.clone()) {
+        (Some(client_id), existing_oauth) => Some(config::OAuthConfig {
+            client_secret: oauth_client_secret
+                .or(existing_oauth
+                    .filter(|o| o.client_id == client_id)
+                    .map(|o| o.client_secret))
+                .unwrap_or_default(),
+            client_id,
+        }),
+        (None, Some(existing_oauth)) => Some(config::OAuthConfig {
+            client_secret: oauth_client_secret.unwrap_or(existing_oauth.client_secret),
+            ..existing_oauth
+        }),
+        (None, None) => None,
+    };
+    if auth == config::AuthMethod::Oauth && oauth.is_none() {
+        tracing::error!(
+            "OAuth needs a client, pass {}",
+            Colour::Green.bold().paint("--oauth-client-id")
+        );
+        std::process::exit(1);
+    }
     let mut config = cli::config::Config {
+        profile,
         id,
         instance,
         sn_instance,
         sn_username,
-        sn_password,
+        sn_password: String::new(),
         bin: "".to_string(),
+        credential_store,
+        password_command,
+        auth,
+        oauth,
+        ..existing
     };
-    let sn_client = ServiceNow::new(
-        &config.sn_username,
-        &config.sn_password,
-        &config.sn_instance,
-    );
-    if bin.is_none() {
+    match credential_store {
+        CredentialStore::Command | CredentialStore::Env => {
+            if credential_store == CredentialStore::Command && config.password_command.is_none() {
+                config.password_command = Some(args::write_password_command());
+            }
+            if let Err(e) = config.load_credentials() {
+                tracing::error!("Unable to load credentials: {}", e);
+                std::process::exit(2);
+            }
+        }
+        CredentialStore::Config | CredentialStore::Encrypted => {
+            config.sn_password = sn_password.unwrap_or_else(args::write_password);
+        }
+    }
+    let sn_client = servicenow_client(&config);
+    if let Some(bin) = bin {
+        config.bin = bin;
+    } else {
         let user_group = sn_client.get_user_group(&config.sn_username).await;
         if user_group.is_err() {
             tracing::error!("Unable to get user group: {:?}", user_group.err());
             std::process::exit(2);
         }
         config.bin = user_group.unwrap();
-    } else {
-        config.bin = bin.unwrap();
     }
     let toml_resp = config.to_toml_file();
     if toml_resp.is_err() {
         tracing::error!("Unable to create config file: {:?}", toml_resp.err());
         std::process::exit(2);
     }
+    tracing::info!("Saved profile {}", config.profile);
+}
+
+async fn run_import(
+    file: std::path::PathBuf,
+    output: Option<std::path::PathBuf>,
+    dry_run: bool,
+    yes: bool,
+) {
+    let rows = import::read_rows(&file).unwrap_or_else(|e| {
+        tracing::error!("Unable to read {}: {}", file.display(), e);
+        std::process::exit(1);
+    });
+    if rows.is_empty() {
+        tracing::error!("{} has no rows to import", file.display());
+        std::process::exit(1);
+    }
+    let output = output.unwrap_or(import::get_results_path(&file));
+    // Stops the import when the results cannot be kept, so no row is posted without a record of it
+    let write_results = |results: &[import::ImportResult]| {
+        if let Err(e) = import::write_results(&output, results) {
+            tracing::error!("Unable to write results to {}: {}", output.display(), e);
+            std::process::exit(2);
+        }
+    };
+    let (config, sn_client) = check_config();
+    let categories = load_categories(&sn_client, &config).await;
+    let validated = import::validate_rows(&sn_client, &categories, &rows)
+        .await
+        .unwrap_or_else(|e| {
+            tracing::error!("Unable to look up tickets: {}", e);
+            std::process::exit(2);
+        });
+
+    // Nothing is posted unless every row is valid
+    if validated.iter().any(|v| v.is_err()) {
+        let mut results = Vec::new();
+        for (i, (row, valid)) in rows.iter().zip(&validated).enumerate() {
+            let mut result = import::ImportResult::new(i + 1, row, "skipped");
+            if let Err(e) = valid {
+                tracing::error!("Row {}: {}", i + 1, e);
+                result.status = "invalid".to_string();
+                result.error = e.clone();
+            }
+            results.push(result);
+        }
+        tracing::error!("Nothing was imported, fix the invalid rows and try again");
+        write_results(&results);
+        tracing::info!("Results written to {}", output.display());
+        std::process::exit(1);
+    }
+    let valid: Vec<import::ValidRow> = validated.into_iter().flatten().collect();
+
+    let mut per_day: std::collections::BTreeMap<String, (i64, usize)> = Default::default();
+    for row in &valid {
+        let day = per_day
+            .entry(row.entry.date.clone().unwrap_or_default())
+            .or_default();
+        day.0 += row.seconds;
+        day.1 += 1;
+    }
+    let total: i64 = valid.iter().map(|r| r.seconds).sum();
+    println!(
+        "{} entries, {} in total",
+        valid.len(),
+        Colour::Green.paint(timetrack::seconds_to_duration(total))
+    );
+    for (day, (seconds, count)) in &per_day {
+        println!(
+            "  {}  {}  {} {}",
+            day,
+            Colour::Green.paint(format!("{:>8}", timetrack::seconds_to_duration(*seconds))),
+            count,
+            if *count == 1 { "entry" } else { "entries" }
+        );
+    }
+    if dry_run || !(yes || args::confirm_import()) {
+        return;
+    }
+
+    // Rewritten after every post, so an interrupted import shows which rows are still pending
+    let mut results: Vec<import::ImportResult> = rows
+        .iter()
+        .enumerate()
+        .map(|(i, row)| import::ImportResult::new(i + 1, row, "pending"))
+        .collect();
+    write_results(&results);
+    let mut failed = 0;
+    for (i, valid) in valid.iter().enumerate() {
+        match timetrack::track_time(&sn_client, &valid.entry).await {
+            Ok(sys_id) => {
+                results[i].status = "tracked".to_string();
+                results[i].sys_id = sys_id;
+            }
+            Err(e) => {
+                tracing::error!("Row {} ({}): {}", i + 1, valid.label, e);
+                // ServiceNow may have created the record before a timeout or 5xx, so the row
+                // must not be imported again as if it failed
+                let uncertain = e.is_transient() && !e.is_unsent();
+                results[i].status = if uncertain { "unknown" } else { "failed" }.to_string();
+                results[i].error = e.to_string();
+                failed += 1;
+            }
+        }
+        write_results(&results);
+    }
+    tracing::info!(
+        "Imported {} of {} entries",
+        results.len() - failed,
+        results.len()
+    );
+    tracing::info!("Results written to {}", output.display());
+    if results.iter().any(|r| r.status == "unknown") {
+        log_maybe_tracked();
+    }
+    if failed > 0 {
+        std::process::exit(2);
+    }
+}
+
+async fn run_fav(cmd: args::FavCommands) {
+    let history = history::History::from_toml_file();
+    if let Err(e) = history {
+        tracing::error!("Unable to read ticket history: {:?}", e);
+        std::process::exit(2);
+    }
+    let mut history = history.unwrap();
+    match cmd {
+        args::FavCommands::Add { ticket } => {
+            let (_config, sn_client) = check_config();
+            let found = sn_client.get_ticket_by_number(&ticket).await;
+            if let Err(e) = found {
+                tracing::error!("Unable to find ticket {}: {}", ticket, e);
+                std::process::exit(if e.is_transient() { 2 } else { 1 });
+            }
+            let found = found.unwrap();
+            history.add_favourite(&found.sys_id, &found.number, &found.short_description);
+            tracing::info!("Added {} to favourites", found.get_debug_string());
+        }
+        args::FavCommands::Rm { ticket } => {
+            if !history.remove_favourite(&ticket) {
+                tracing::error!("{} is not a favourite", ticket);
+                std::process::exit(1);
+            }
+            tracing::info!("Removed {} from favourites", ticket);
+        }
+        args::FavCommands::List => {
+            let favourites = history.favourites();
+            let recent = history.recent(history::RECENT_LIMIT);
+            if favourites.is_empty() && recent.is_empty() {
+                println!("No favourite or recent tickets");
+            }
+            for entry in favourites.into_iter().chain(recent) {
+                println!(
+                    "{} {}",
+                    entry.label(),
+                    Colour::Fixed(244).paint(format!("({})", entry.usage()))
+                );
+            }
+            std::process::exit(0);
+        }
+    }
+    if let Err(e) = history.to_toml_file() {
+        tracing::error!("Unable to save ticket history: {:?}", e);
+        std::process::exit(2);
+    }
+    std::process::exit(0);
+}
+
+async fn run_config(cmd: args::ConfigCommands) {
+    if let args::ConfigCommands::Path = cmd {
+        println!("{}", config::get_config_path().display());
+        std::process::exit(0);
+    }
+    let mut config = load_config();
+    match cmd {
+        args::ConfigCommands::Show => {
+            let shown = toml::to_string(&config.masked());
+            if let Err(e) = shown {
+                tracing::error!("Unable to show config: {}", e);
+                std::process::exit(2);
+            }
+            println!("# profile {}", config.profile);
+            print!("{}", shown.unwrap());
+        }
+        args::ConfigCommands::Get { key } => match config.get_value(&key) {
+            Ok(Some(toml::Value::String(value))) => println!("{}", value),
+            Ok(Some(value)) => println!("{}", value),
+            Ok(None) => {
+                tracing::error!("{} is not set in profile {}", key, config.profile);
+                std::process::exit(1);
+            }
+            Err(e) => {
+                tracing::error!("{}", e);
+                std::process::exit(1);
+            }
+        },
+        args::ConfigCommands::Set { values } => {
+            // secrets.enc is rewritten on save, so it has to be unlocked first. Secrets are read
+            // from the old store when changing stores, so they can be moved to the new one
+            let old_store = config.credential_store;
+            let changes_store = values.iter().any(|(key, _)| key == "credential_store");
+            if old_store == CredentialStore::Encrypted || changes_store {
+                if let Err(e) = config.load_credentials() {
+                    tracing::error!("Unable to load {:?} credentials: {}", old_store, e);
+                    std::process::exit(2);
+                }
+            }
+            for (key, value) in values {
+                if let Err(e) = config.set_value(&key, &value) {
+                    tracing::error!("{}", e);
+                    std::process::exit(1);
+                }
+            }
+            if config.credential_store != old_store && config.sn_password.is_empty() {
+                tracing::error!(
+                    "The {:?} store has no ServiceNow password to move, run {} instead",
+                    old_store,
+                    Colour::Green.bold().paint("elasticnow setup")
+                );
+                std::process::exit(1);
+            }
+            if config.credential_store == CredentialStore::Command
+                && config.password_command.is_none()
+            {
+                tracing::error!("The command store needs password_command to be set");
+                std::process::exit(1);
+            }
+            if let Err(e) = config.to_toml_file() {
+                tracing::error!("Unable to save config file: {:?}", e);
+                std::process::exit(2);
+            }
+            tracing::info!("Saved profile {}", config.profile);
+        }
+        args::ConfigCommands::Validate => {
+            if !validate_config(config).await {
+                std::process::exit(1);
+            }
+        }
+        args::ConfigCommands::Path => unreachable!(),
+    }
+    std::process::exit(0);
+}
+
+// Prints a line per check and returns whether all of them passed
+async fn validate_config(mut config: config::Config) -> bool {
+    let report = |check: String, result: Result<(), String>| match &result {
+        Ok(()) => {
+            println!("{} {}", Colour::Green.paint("ok    "), check);
+            true
+        }
+        Err(e) => {
+            println!("{} {}: {}", Colour::Red.paint("failed"), check, e);
+            false
+        }
+    };
+    let loaded = config.load_credentials().map_err(|e| e.to_string());
+    if !report(format!("{:?} credentials", config.credential_store), loaded) {
+        return false;
+    }
+    let sn_client = servicenow_client(&config);
+    let sn_auth = sn_client
+        .get_user_group(&config.sn_username)
+        .await
+        .map(|_| ())
+        .map_err(|e| e.to_string());
+    let mut valid = report(
+        format!("ServiceNow login as {}", config.sn_username),
+        sn_auth,
+    );
+    // The bin lookup fails the same way when the login does, so it is only checked after one
+    if valid {
+        let bin = sn_client
+            .check_group(&config.bin)
+            .await
+            .map_err(|e| e.to_string());
+        valid &= report(format!("Bin {}", config.bin), bin);
+    }
+    let es_auth = ElasticNow::new(&config.id, &config.instance)
+        .with_retry(config.retry.clone())
+        .check_auth()
+        .await
+        .map_err(|e| e.to_string());
+    valid &= report(format!("ElasticNow ID for {}", config.instance), es_auth);
+    valid
 }
 
 async fn run_stdchg(search: String, bin: Option<String>, template_id: Option<String>) {
@@ -278,35 +1106,30 @@ async fn run_stdchg(search: String, bin: Option<String>, template_id: Option<Str
     tracing::debug!("Bin: {:?}", bin);
     tracing::debug!("Template ID: {:?}", template_id);
     let bin = bin.unwrap_or(config.bin.clone());
-    let template_sys_id: String;
-    if template_id.is_none() {
-        let std_changes_resp = sn_client.search_std_chg(&search).await;
-        if std_changes_resp.is_err() {
-            tracing::error!("Unable to search std chgs: {:?}", std_changes_resp.err());
-            std::process::exit(2);
-        }
-        let std_changes = std_changes_resp.unwrap();
-        if std_changes.is_empty() {
+    let resp = create::create_std_chg(
+        &sn_client,
+        &search,
+        &bin,
+        template_id,
+        cli::args::choose_chg_template,
+    )
+    .await;
+    let sys_id = match resp {
+        Ok(sys_id) => sys_id,
+        Err(Error::NotFound { .. }) => {
             tracing::error!("No std chgs found for search: {}", search);
             std::process::exit(1);
         }
-        template_sys_id = cli::args::choose_chg_template(std_changes);
-    } else {
-        template_sys_id = template_id.unwrap();
-    }
-    tracing::debug!("Selected chg_id: {}", template_sys_id);
-    let resp = sn_client
-        .create_std_chg_from_template(&template_sys_id, &bin)
-        .await;
-    if resp.is_err() {
-        tracing::error!("Unable to create std chg: {:?}", resp.err());
-        std::process::exit(2);
-    }
-    let sys_id = resp.unwrap();
+        Err(e) => {
+            tracing::error!("Unable to create std chg: {}", e);
+            std::process::exit(2);
+        }
+    };
     tracing::info!("Created std chg: {}", sys_id);
-    let ticket_url = ansi_term::Colour::Blue.paint(format!(
-        "https://{}.service-now.com/change_request.do?sys_id={}",
-        &config.sn_instance, sys_id
+    let ticket_url = ansi_term::Colour::Blue.paint(servicenow::record_link(
+        &config.sn_instance,
+        "change_request",
+        &sys_id,
     ));
     println!("Link to CHG: {}", ticket_url);
 
@@ -314,24 +1137,31 @@ async fn run_stdchg(search: String, bin: Option<String>, template_id: Option<Str
 }
 
 //Returns the sys_id of new ticket
-async fn new_ticket(sn_client: &ServiceNow, config: &config::Config) -> String {
+async fn new_ticket(sn_client: &impl TicketBackend, config: &config::Config) -> String {
     let desc = cli::args::write_short_description();
-    tracing::debug!("Creating new ticket: {}", &desc);
-    let resp = sn_client.create_ticket(&config.bin, &desc).await;
-    if resp.is_err() {
-        tracing::error!("Unable to create ticket: {:?}", resp.err());
-        std::process::exit(2);
-    }
-    let sys_id = resp.unwrap();
+    let sys_id = match create::create_ticket(sn_client, &config.bin, &desc).await {
+        Ok(sys_id) => sys_id,
+        Err(e @ Error::Validation(_)) => {
+            tracing::error!("{}", e);
+            std::process::exit(1);
+        }
+        Err(e) => {
+            tracing::error!("Unable to create ticket: {}", e);
+            std::process::exit(2);
+        }
+    };
     tracing::info!(
-        "Created ticket: https://{}.service-now.com/sc_req_item.do?sys_id={}",
-        &config.sn_instance,
-        sys_id
+        "Created ticket: {}",
+        servicenow::record_link(&config.sn_instance, "sc_req_item", &sys_id)
     );
-    return sys_id;
+    sys_id
 }
 
-async fn search_tickets(es_now_client: ElasticNow, bin: &str, keywords: &str) -> Vec<SearchResult> {
+async fn search_tickets(
+    es_now_client: impl TicketSearch,
+    bin: &str,
+    keywords: &str,
+) -> Vec<SearchResult> {
     let resp = es_now_client.get_keyword_tickets(keywords, bin).await;
     if resp.is_err() {
         tracing::error!("Unable to search tickets: {:?}", resp.err());
@@ -340,58 +1170,104 @@ async fn search_tickets(es_now_client: ElasticNow, bin: &str, keywords: &str) ->
     resp.unwrap()
 }
 
-fn generic_options_to_value_option<T: ChooseOptions<T>>(result: &Vec<T>) -> Vec<ValueOption> {
-    let mut value_vec: Vec<ValueOption> = Vec::new();
-    for r in result {
-        let val_opt = ValueOption {
-            display_value: r.get_number(),
+fn generic_options_to_value_option<T: ChooseOptions<T>>(result: &[T]) -> Vec<ValueOption> {
+    result
+        .iter()
+        .map(|r| ValueOption {
+            display_value: r.get_debug_string(),
             value: r.get_id(),
-        };
-        value_vec.push(val_opt);
-    }
-    value_vec
+            number: r.get_number(),
+            short_description: r.get_short_description(),
+            preview: r.get_preview(),
+        })
+        .collect()
 }
 
-fn search_results_to_string<T: ChooseOptions<T>>(result: &Vec<T>) -> Vec<String> {
-    let mut string_vec: Vec<String> = Vec::new();
-    for r in result {
-        string_vec.push(r.get_debug_string());
-    }
-    string_vec
+fn history_to_value_option(entries: &[&history::HistoryEntry]) -> Vec<ValueOption> {
+    entries
+        .iter()
+        .map(|e| ValueOption {
+            display_value: e.label(),
+            value: e.sys_id.clone(),
+            number: e.number.clone(),
+            short_description: e.short_description.clone(),
+            preview: vec![e.short_description.clone(), e.usage()],
+        })
+        .collect()
 }
 
 fn get_search_result_from_input(input: &str, result: Vec<ValueOption>) -> Option<ValueOption> {
-    for r in result {
-        if input.starts_with(&r.display_value) {
-            return Some(r);
-        }
-    }
-    None
+    result.into_iter().find(|r| r.display_value == input)
 }
 
 fn check_config() -> (config::Config, ServiceNow) {
-    let config = config::Config::from_toml_file();
-    if config.is_err() {
+    let mut config = load_config();
+    if let Err(e) = config.load_credentials() {
         tracing::error!(
-            "Unable to load config file. Please run {} and try again.",
-            Colour::Green.bold().paint("elasticnow setup")
+            "Unable to load {:?} credentials: {}",
+            config.credential_store,
+            e
         );
         std::process::exit(2);
     }
-    let config = config.unwrap();
+    let sn_client = servicenow_client(&config);
+    (config, sn_client)
+}
+
+// Exits when the selected profile cannot be read, pointing at setup only when it is missing
+fn load_config() -> config::Config {
+    match config::Config::from_toml_file() {
+        Ok(config) => config,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            tracing::error!(
+                "Unable to load config file: {}. Please run {} and try again.",
+                e,
+                Colour::Green.bold().paint("elasticnow setup")
+            );
+            std::process::exit(2);
+        }
+        Err(e) => {
+            tracing::error!("{}", e);
+            std::process::exit(2);
+        }
+    }
+}
+
+async fn load_categories(
+    sn_client: &impl TimeWorkedBackend,
+    config: &config::Config,
+) -> Vec<Choice> {
+    categories::load_categories(
+        sn_client,
+        &config.categories,
+        &categories::get_categories_path(&config.profile),
+    )
+    .await
+}
+
+fn servicenow_client(config: &config::Config) -> ServiceNow {
     let sn_client = ServiceNow::new(
         &config.sn_username,
         &config.sn_password,
         &config.sn_instance,
-    );
-    (config, sn_client)
-}
-
-fn get_total(tasks: &Vec<TimeWorked>) -> i64 {
-    tasks
-        .iter()
-        .map(|t| t.time_in_seconds.parse::<i64>().unwrap_or_default())
-        .sum()
+    )
+    .with_retry(config.retry.clone());
+    if config.auth == config::AuthMethod::Basic {
+        return sn_client;
+    }
+    let Some(oauth) = &config.oauth else {
+        tracing::error!("auth is set to oauth but the [oauth] table is missing from the config");
+        std::process::exit(2);
+    };
+    sn_client.with_oauth(OAuth::new(
+        &oauth.client_id,
+        &oauth.client_secret,
+        Some(config::get_profile_path(
+            &config.profile,
+            "oauth_token",
+            "toml",
+        )),
+    ))
 }
 
 fn get_cookie_from_browser(elasticnow_url: &str) -> String {
@@ -432,12 +1308,11 @@ fn get_cookie_from_browser(elasticnow_url: &str) -> String {
 
     // Set up the local server to capture the cookie
     let mut _id = "".to_string();
-    for request in server.incoming_requests() {
+    if let Some(request) = server.incoming_requests().next() {
         let response = Response::from_string("Login successful. You can close this window.");
         _id = request.url().to_string();
         _id.remove(0);
         request.respond(response).unwrap();
-        break;
     }
     tracing::info!("Got cookie: {}", _id);
     _id.to_string()
@@ -0,0 +1,341 @@
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use tiny_http::{Header, Response, Server};
+
+/// ElasticNow session id accepted by `/cli/login`
+pub const VALID_SESSION: &str = "valid-session";
+/// OAuth client and password accepted by `/oauth_token.do`
+pub const OAUTH_CLIENT_ID: &str = "client";
+pub const SN_PASSWORD: &str = "hunter2";
+
+#[derive(Debug, Clone)]
+pub struct RecordedRequest {
+    pub method: String,
+    /// Path and query string as sent by the client
+    pub url: String,
+    pub body: String,
+    pub authorization: String,
+}
+
+impl RecordedRequest {
+    pub fn json(&self) -> serde_json::Value {
+        serde_json::from_str(&self.body).unwrap()
+    }
+}
+
+/// Local stand-in for the ServiceNow Table API and ElasticNow, serving the fixtures in tests/fixtures
+pub struct FakeServer {
+    /// Base url to hand to the clients, e.g. http://127.0.0.1:41234
+    pub url: String,
+    requests: Arc<Mutex<Vec<RecordedRequest>>>,
+    // Status returned for every request instead of the fixture, to simulate outages
+    fail_with: Arc<Mutex<Option<u16>>>,
+    // Statuses returned for the next requests, one each, before routing resumes. None routes the
+    // request as usual
+    fail_next: Arc<Mutex<VecDeque<Option<u16>>>>,
+    // OAuth access and refresh tokens that are currently accepted
+    tokens: Arc<Mutex<OAuthTokens>>,
+    server: Arc<Server>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl FakeServer {
+    pub fn start() -> Self {
+        let server = Arc::new(Server::http("127.0.0.1:0").unwrap());
+        let url = format!("http://{}", server.server_addr().to_ip().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let fail_with = Arc::new(Mutex::new(None));
+        let fail_next = Arc::new(Mutex::new(VecDeque::new()));
+        let tokens = Arc::new(Mutex::new(OAuthTokens::default()));
+        let handle = {
+            let tokens = tokens.clone();
+            let server = server.clone();
+            let requests = requests.clone();
+            let fail_with = fail_with.clone();
+            let fail_next = fail_next.clone();
+            std::thread::spawn(move || {
+                for mut request in server.incoming_requests() {
+                    let mut body = String::new();
+                    let _ = request.as_reader().read_to_string(&mut body);
+                    let header = |name: &'static str| {
+                        request
+                            .headers()
+                            .iter()
+                            .find(|h| h.field.equiv(name))
+                            .map(|h| h.value.to_string())
+                            .unwrap_or_default()
+                    };
+                    let cookie = header("Cookie");
+                    let recorded = RecordedRequest {
+                        method: request.method().to_string(),
+                        url: request.url().to_string(),
+                        body,
+                        authorization: header("Authorization"),
+                    };
+                    let failure = fail_next.lock().unwrap().pop_front().flatten();
+                    let (status, payload) = match failure.or(*fail_with.lock().unwrap()) {
+                        Some(status) => (status, "{}".to_string()),
+                        None => tokens
+                            .lock()
+                            .unwrap()
+                            .check(&recorded)
+                            .unwrap_or_else(|| route(&recorded, &cookie)),
+                    };
+                    requests.lock().unwrap().push(recorded);
+                    let mut response = Response::from_string(payload)
+                        .with_status_code(status)
+                        .with_header(
+                            Header::from_bytes("Content-Type", "application/json").unwrap(),
+                        );
+                    if status == 429 {
+                        response.add_header(Header::from_bytes("Retry-After", "0").unwrap());
+                    }
+                    let _ = request.respond(response);
+                }
+            })
+        };
+        Self {
+            url,
+            requests,
+            fail_with,
+            fail_next,
+            tokens,
+            server,
+            handle: Some(handle),
+        }
+    }
+
+    pub fn fail_with(&self, status: Option<u16>) {
+        *self.fail_with.lock().unwrap() = status;
+    }
+
+    pub fn fail_next(&self, statuses: &[u16]) {
+        self.fail_next
+            .lock()
+            .unwrap()
+            .extend(statuses.iter().copied().map(Some));
+    }
+
+    // Routes the next `skip` requests as usual and fails the one after them
+    pub fn fail_after(&self, skip: usize, status: u16) {
+        let mut fail_next = self.fail_next.lock().unwrap();
+        fail_next.extend(std::iter::repeat_n(None, skip));
+        fail_next.push_back(Some(status));
+    }
+
+    // Rejects every access token issued so far, refresh tokens stay valid
+    pub fn revoke_access_tokens(&self) {
+        self.tokens.lock().unwrap().access.clear();
+    }
+
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    // Requests whose path (without the query string) matches
+    pub fn requests_to(&self, method: &str, path: &str) -> Vec<RecordedRequest> {
+        self.requests()
+            .into_iter()
+            .filter(|r| r.method == method && r.url.split('?').next() == Some(path))
+            .collect()
+    }
+}
+
+impl Drop for FakeServer {
+    fn drop(&mut self) {
+        self.server.unblock();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+#[derive(Default)]
+struct OAuthTokens {
+    issued: usize,
+    access: Vec<String>,
+    refresh: Vec<String>,
+}
+
+impl OAuthTokens {
+    // Answers /oauth_token.do and rejects unknown bearer tokens, other requests are routed
+    fn check(&mut self, request: &RecordedRequest) -> Option<(u16, String)> {
+        if request.url == "/oauth_token.do" {
+            let form: std::collections::HashMap<String, String> =
+                url::form_urlencoded::parse(request.body.as_bytes())
+                    .into_owned()
+                    .collect();
+            let field = |name: &str| form.get(name).map(String::as_str).unwrap_or_default();
+            let granted = field("client_id") == OAUTH_CLIENT_ID
+                && match field("grant_type") {
+                    "password" => field("password") == SN_PASSWORD,
+                    "refresh_token" => self.refresh.iter().any(|t| t == field("refresh_token")),
+                    _ => false,
+                };
+            if !granted {
+                return Some((401, r#"{"error": "access_denied"}"#.to_string()));
+            }
+            self.issued += 1;
+            let access_token = format!("access-{}", self.issued);
+            let refresh_token = format!("refresh-{}", self.issued);
+            self.access.push(access_token.clone());
+            self.refresh.push(refresh_token.clone());
+            return Some((
+                200,
+                serde_json::json!({
+                    "access_token": access_token,
+                    "refresh_token": refresh_token,
+                    "token_type": "Bearer",
+                    "expires_in": 1799,
+                })
+                .to_string(),
+            ));
+        }
+        let token = request.authorization.strip_prefix("Bearer ")?;
+        if self.access.iter().any(|t| t == token) {
+            return None;
+        }
+        Some((
+            401,
+            r#"{"error": {"message": "Invalid token"}}"#.to_string(),
+        ))
+    }
+}
+
+fn route(request: &RecordedRequest, cookie: &str) -> (u16, String) {
+    let path = request.url.split('?').next().unwrap_or_default();
+    let fixture = match (request.method.as_str(), path) {
+        ("GET", "/cli/login") if cookie.contains(&format!("id={}", VALID_SESSION)) => {
+            return (200, "{}".to_string())
+        }
+        ("GET", "/cli/login") => return (401, "{}".to_string()),
+        ("POST", p) if p.starts_with("/tickets/") => include_str!("../fixtures/tickets.json"),
+        // Lookups by number or sys_id only return the matching tasks
+        ("GET", "/api/now/table/task")
+            if request.url.contains("number=") || request.url.contains("sys_idIN") =>
+        {
+            let sys_ids: Vec<&str> = request
+                .url
+                .split("sys_idIN")
+                .nth(1)
+                .and_then(|listed| listed.split('&').next())
+                .map(|listed| listed.split(',').collect())
+                .unwrap_or_default();
+            let tasks: serde_json::Value =
+                serde_json::from_str(include_str!("../fixtures/task.json")).unwrap();
+            let matching: Vec<&serde_json::Value> = tasks["result"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .filter(|t| {
+                    request
+                        .url
+                        .contains(&format!("number={}&", t["number"].as_str().unwrap()))
+                        || sys_ids.contains(&t["sys_id"].as_str().unwrap())
+                })
+                .collect();
+            return (200, serde_json::json!({ "result": matching }).to_string());
+        }
+        ("GET", "/api/now/table/task") => include_str!("../fixtures/task.json"),
+        ("GET", "/api/now/table/task_time_worked") => {
+            include_str!("../fixtures/task_time_worked.json")
+        }
+        ("POST", "/api/now/table/task_time_worked") => {
+            return (
+                201,
+                include_str!("../fixtures/task_time_worked_created.json").to_string(),
+            )
+        }
+        ("DELETE", p) if p.starts_with("/api/now/table/task_time_worked/") => {
+            return (204, String::new())
+        }
+        ("GET", "/api/now/table/task_cost_center") => {
+            include_str!("../fixtures/task_cost_center.json")
+        }
+        ("GET", "/api/now/table/sys_user") => include_str!("../fixtures/sys_user.json"),
+        ("GET", "/api/now/table/sys_choice") => include_str!("../fixtures/sys_choice.json"),
+        // Linux is the only assignment group
+        ("GET", "/api/now/table/sys_user_group") if request.url.contains("name=Linux&") => {
+            r#"{"result": [{"sys_id": "group1"}]}"#
+        }
+        ("GET", "/api/now/table/sys_user_group") => r#"{"result": []}"#,
+        ("POST", "/api/now/table/sc_req_item") => {
+            return (
+                201,
+                include_str!("../fixtures/sc_req_item.json").to_string(),
+            )
+        }
+        ("GET", "/api/now/table/std_change_record_producer") => {
+            include_str!("../fixtures/std_change_record_producer.json")
+        }
+        ("POST", p) if p.starts_with("/api/sn_chg_rest/change/standard/") => {
+            include_str!("../fixtures/std_change.json")
+        }
+        _ => {
+            return (
+                404,
+                r#"{"error": {"message": "No such route"}}"#.to_string(),
+            )
+        }
+    };
+    (200, fixture.to_string())
+}
+
+/// Config directory for running the binary against the fake server, removed on drop
+pub struct TestHome {
+    pub path: PathBuf,
+}
+
+impl TestHome {
+    // Without a config file, for running setup
+    pub fn empty(name: &str) -> Self {
+        let path =
+            std::env::temp_dir().join(format!("elasticnow-test-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(path.join("elasticnow")).unwrap();
+        Self { path }
+    }
+
+    pub fn new(name: &str, server: &FakeServer) -> Self {
+        let home = Self::empty(name);
+        let config = format!(
+            r#"id = "{session}"
+instance = "{url}"
+sn_instance = "{url}"
+sn_username = "jdoe"
+sn_password = "{password}"
+bin = "Linux"
+"#,
+            session = VALID_SESSION,
+            password = SN_PASSWORD,
+            url = server.url
+        );
+        std::fs::write(home.config_file("config.toml"), config).unwrap();
+        home
+    }
+
+    pub fn config_file(&self, name: &str) -> PathBuf {
+        self.path.join("elasticnow").join(name)
+    }
+
+    pub fn command(&self) -> std::process::Command {
+        let mut command = std::process::Command::new(env!("CARGO_BIN_EXE_elasticnow"));
+        command
+            .env("XDG_CONFIG_HOME", &self.path)
+            .env("HOME", &self.path)
+            .env_remove("ELASTICNOW_LOG_LEVEL")
+            .env_remove("SN_PASSWORD")
+            .env_remove("ELASTICNOW_ID")
+            .env_remove("ELASTICNOW_PASSPHRASE")
+            .env_remove("ELASTICNOW_PROFILE");
+        command
+    }
+}
+
+impl Drop for TestHome {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.path);
+    }
+}
@@ -0,0 +1,1050 @@
+mod common;
+
+use common::{FakeServer, TestHome, OAUTH_CLIENT_ID, SN_PASSWORD, VALID_SESSION};
+use elasticnow::cli::args::ReportBy;
+use elasticnow::cli::categories::default_categories;
+use elasticnow::cli::report::summarize_time_worked;
+use elasticnow::elasticnow::elasticnow::ElasticNow;
+use elasticnow::elasticnow::error::Error;
+use elasticnow::elasticnow::oauth::OAuth;
+use elasticnow::elasticnow::retry::RetryPolicy;
+use elasticnow::elasticnow::servicenow::ServiceNow;
+use std::collections::HashMap;
+
+fn servicenow(server: &FakeServer) -> ServiceNow {
+    ServiceNow::new("jdoe", SN_PASSWORD, &server.url)
+}
+
+#[tokio::test]
+async fn test_servicenow_tickets_and_time_worked() {
+    let server = FakeServer::start();
+    let sn_client = servicenow(&server);
+
+    assert_eq!(sn_client.get_user_group("jdoe").await.unwrap(), "Linux");
+    let tickets = sn_client.get_all_tickets_in_bin("Linux").await.unwrap();
+    assert_eq!(tickets.len(), 2);
+    assert_eq!(tickets[0].number, "RITM0000001");
+
+    sn_client
+        .add_time_to_ticket("sys1", "1h30m", "patching", Some("2024-06-24"))
+        .await
+        .unwrap();
+    sn_client
+        .add_time_to_no_tkt("clerical", "15m", "email", None)
+        .await
+        .unwrap();
+    let posted = server.requests_to("POST", "/api/now/table/task_time_worked");
+    assert_eq!(posted.len(), 2);
+    assert_eq!(posted[0].json()["task"], "sys1");
+    assert_eq!(posted[0].json()["time_worked"], "1970-01-01+01:30:00");
+    assert_eq!(posted[0].json()["u_created_for"], "2024-06-24");
+    assert_eq!(posted[1].json()["u_category"], "clerical");
+    assert!(posted[1].json().get("u_created_for").is_none());
+
+    let summary = summarize_time_worked(
+        &sn_client,
+        "jdoe",
+        "2024-06-24",
+        "2024-06-30",
+        ReportBy::CostCenter,
+        &default_categories(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(summary.total, 10800);
+    assert_eq!(
+        summary.time_worked,
+        HashMap::from([
+            ("IT Infrastructure".to_string(), 5400),
+            ("Security".to_string(), 1800),
+            ("Clerical".to_string(), 3600),
+        ])
+    );
+    let cost_centers = server.requests_to("GET", "/api/now/table/task_cost_center");
+    assert_eq!(cost_centers.len(), 1);
+    assert!(cost_centers[0].url.contains("taskINsys"));
+}
+
+#[tokio::test]
+async fn test_servicenow_create_ticket_and_std_chg() {
+    let server = FakeServer::start();
+    let sn_client = servicenow(&server);
+
+    let sys_id = sn_client.create_ticket("Linux", "New work").await.unwrap();
+    assert_eq!(sys_id, "ritm_new");
+    let created = &server.requests_to("POST", "/api/now/table/sc_req_item")[0];
+    assert_eq!(created.json()["short_description"], "New work");
+    assert_eq!(created.json()["assignment_group"], "Linux");
+
+    let templates = sn_client.search_std_chg("Patch").await.unwrap();
+    assert_eq!(templates[0].sys_id, "tmpl1");
+    let chg = sn_client
+        .create_std_chg_from_template(&templates[0].sys_id, "Linux")
+        .await
+        .unwrap();
+    assert_eq!(chg, "chg_new");
+    let created = &server.requests_to("POST", "/api/sn_chg_rest/change/standard/tmpl1")[0];
+    assert_eq!(created.json()["assignment_group"], "Linux");
+}
+
+#[tokio::test]
+async fn test_servicenow_errors() {
+    let server = FakeServer::start();
+    let sn_client = servicenow(&server);
+
+    server.fail_with(Some(401));
+    assert!(sn_client
+        .get_user_group("jdoe")
+        .await
+        .unwrap_err()
+        .is_auth());
+    server.fail_with(Some(503));
+    let err = sn_client
+        .add_time_to_ticket("sys1", "1h", "patching", None)
+        .await
+        .unwrap_err();
+    assert!(err.is_transient() && !err.is_unsent());
+}
+
+#[tokio::test]
+async fn test_retries() {
+    let server = FakeServer::start();
+    let sn_client = servicenow(&server).with_retry(RetryPolicy {
+        max_attempts: 3,
+        base_delay_ms: 1,
+        timeout_secs: 10,
+    });
+
+    server.fail_next(&[503, 429]);
+    assert_eq!(sn_client.get_user_group("jdoe").await.unwrap(), "Linux");
+    assert_eq!(
+        server.requests_to("GET", "/api/now/table/sys_user").len(),
+        3
+    );
+
+    server.fail_next(&[429, 429, 429]);
+    let err = sn_client.get_user_group("jdoe").await.unwrap_err();
+    assert!(matches!(
+        err,
+        Error::RateLimited {
+            retry_after: Some(0),
+            ..
+        }
+    ));
+
+    // A post that reached the instance may have created the record, so 5xx is not retried
+    server.fail_next(&[503]);
+    let err = sn_client
+        .add_time_to_ticket("sys1", "1h", "patching", None)
+        .await
+        .unwrap_err();
+    assert!(err.is_transient());
+    server.fail_next(&[429]);
+    sn_client
+        .add_time_to_ticket("sys1", "1h", "patching", None)
+        .await
+        .unwrap();
+    assert_eq!(
+        server
+            .requests_to("POST", "/api/now/table/task_time_worked")
+            .len(),
+        3
+    );
+
+    let es_client = ElasticNow::new(VALID_SESSION, &server.url).with_retry(sn_client.retry.clone());
+    server.fail_next(&[502]);
+    assert_eq!(
+        es_client
+            .get_keyword_tickets("web", "Linux")
+            .await
+            .unwrap()
+            .len(),
+        1
+    );
+}
+
+#[tokio::test]
+async fn test_elasticnow_login_and_search() {
+    let server = FakeServer::start();
+    assert!(ElasticNow::new(VALID_SESSION, &server.url)
+        .check_auth()
+        .await
+        .is_ok());
+    let expired = ElasticNow::new("expired", &server.url).check_auth().await;
+    assert!(expired.unwrap_err().is_auth());
+
+    let results = ElasticNow::new(VALID_SESSION, &server.url)
+        .get_keyword_tickets("web servers", "Linux")
+        .await
+        .unwrap();
+    assert_eq!(results[0].source.number, "RITM0000001");
+    let search = &server.requests_to("POST", "/tickets/web%20servers")[0];
+    assert_eq!(search.json()[0]["term"]["assignment_group"], "Linux");
+}
+
+#[test]
+fn test_binary_report() {
+    let server = FakeServer::start();
+    let home = TestHome::new("report", &server);
+    let output = home
+        .command()
+        .args(["report", "--by", "ticket", "--format", "json"])
+        .args(["--since", "2024-06-24", "--until", "2024-06-30"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(report["total_seconds"], 10800);
+    assert_eq!(report["rows"][0]["name"], "RITM0000001: Patch web servers");
+    assert_eq!(report["rows"][0]["seconds"], 5400);
+    let lookup = &server.requests_to("GET", "/api/now/table/task")[0];
+    assert!(lookup.url.contains("sys_idIN"));
+
+    let output = home
+        .command()
+        .args(["report", "--target", "7"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2), "--target needs --daily");
+
+    // The chart's last label is --until, not the day after it
+    let output = home
+        .command()
+        .args(["report", "--daily", "--target", "7"])
+        .args(["--since", "2024-06-24", "--until", "2024-06-30"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Hours per day"), "{}", stdout);
+    assert!(!stdout.contains("2024-07-01"), "{}", stdout);
+}
+
+#[test]
+fn test_binary_sync_and_std_chg() {
+    let server = FakeServer::start();
+    let home = TestHome::new("sync", &server);
+    std::fs::write(
+        home.config_file("queue.toml"),
+        r#"[[entries]]
+sys_id = "sys1"
+time_worked = "45m"
+comment = "patching"
+date = "2024-06-21"
+"#,
+    )
+    .unwrap();
+    let output = home.command().arg("sync").output().unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let posted = server.requests_to("POST", "/api/now/table/task_time_worked");
+    assert_eq!(posted[0].json()["u_created_for"], "2024-06-21");
+    let queue = std::fs::read_to_string(home.config_file("queue.toml")).unwrap();
+    assert!(!queue.contains("sys1"));
+
+    let output = home
+        .command()
+        .args(["std-chg", "--template-id", "tmpl1"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    assert!(String::from_utf8_lossy(&output.stdout)
+        .contains(&format!("{}/change_request.do?sys_id=chg_new", server.url)));
+}
+
+#[test]
+fn test_binary_offline_queue() {
+    let server = FakeServer::start();
+    let home = TestHome::new("offline", &server);
+    let track = |home: &TestHome| {
+        home.command()
+            .args(["timetrack", "--no-tkt", "--category", "clerical"])
+            .args(["-t", "45m", "-c", "email", "--date", "2024-06-21"])
+            .output()
+            .unwrap()
+    };
+
+    // A 5xx may come after the record was created, so the entry is not queued. The categories are
+    // read first
+    server.fail_after(1, 503);
+    let output = track(&home);
+    assert_eq!(output.status.code(), Some(2), "{:?}", output);
+    assert!(
+        String::from_utf8_lossy(&output.stdout).contains("may have tracked the time anyway"),
+        "{:?}",
+        output
+    );
+    assert!(!home.config_file("queue.toml").exists());
+
+    // Nothing listens on the instance after the server is dropped
+    let offline = FakeServer::start();
+    let offline_home = TestHome::new("offline-unreachable", &offline);
+    drop(offline);
+    let mut config = std::fs::read_to_string(offline_home.config_file("config.toml")).unwrap();
+    config += "[retry]\nmax_attempts = 1\n";
+    std::fs::write(offline_home.config_file("config.toml"), config).unwrap();
+    let output = track(&offline_home);
+    assert!(output.status.success(), "{:?}", output);
+    let queue = std::fs::read_to_string(offline_home.config_file("queue.toml")).unwrap();
+    assert!(queue.contains("clerical"));
+
+    // Replayed entries that fail the same way are dropped from the queue instead of retried
+    std::fs::copy(
+        offline_home.config_file("queue.toml"),
+        home.config_file("queue.toml"),
+    )
+    .unwrap();
+    server.fail_next(&[503]);
+    let output = home.command().arg("sync").output().unwrap();
+    assert_eq!(output.status.code(), Some(2), "{:?}", output);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Unknown") && stdout.contains("45m on 2024-06-21 for clerical"));
+    let queue = std::fs::read_to_string(home.config_file("queue.toml")).unwrap();
+    assert!(!queue.contains("clerical"));
+}
+
+fn basic_auth(username: &str, password: &str) -> String {
+    format!(
+        "Basic {}",
+        openssl::base64::encode_block(format!("{}:{}", username, password).as_bytes())
+    )
+}
+
+fn setup_command(home: &TestHome, server: &FakeServer) -> std::process::Command {
+    let mut command = home.command();
+    command
+        .args(["setup", "--id", VALID_SESSION, "--sn-username", "jdoe"])
+        .args(["--instance", &server.url, "--sn-instance", &server.url]);
+    command
+}
+
+#[test]
+fn test_binary_setup_env_and_command_stores() {
+    let server = FakeServer::start();
+    let home = TestHome::empty("setup-env");
+    let output = setup_command(&home, &server)
+        .args(["--credential-store", "env"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success(), "SN_PASSWORD is required");
+
+    let output = setup_command(&home, &server)
+        .env("SN_PASSWORD", "from-env")
+        .args(["--credential-store", "env"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let user_lookup = &server.requests_to("GET", "/api/now/table/sys_user")[0];
+    assert_eq!(user_lookup.authorization, basic_auth("jdoe", "from-env"));
+    let config = std::fs::read_to_string(home.config_file("config.toml")).unwrap();
+    assert!(config.contains("credential_store = \"env\""));
+    assert!(config.contains("bin = \"Linux\""));
+    assert!(!config.contains("from-env") && !config.contains(VALID_SESSION));
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let metadata = std::fs::metadata(home.config_file("config.toml")).unwrap();
+        assert_eq!(metadata.permissions().mode() & 0o777, 0o600);
+    }
+
+    let home = TestHome::empty("setup-command");
+    let output = setup_command(&home, &server)
+        .args(["--credential-store", "command", "--bin", "Linux"])
+        .args(["--password-command", "echo from-command"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let config = std::fs::read_to_string(home.config_file("config.toml")).unwrap();
+    assert!(!config.contains("sn_password"));
+    assert!(config.contains("password_command = \"echo from-command\""));
+    let output = home
+        .command()
+        .args(["std-chg", "--template-id", "tmpl1"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let created = server.requests_to("POST", "/api/sn_chg_rest/change/standard/tmpl1");
+    assert_eq!(created[0].authorization, basic_auth("jdoe", "from-command"));
+}
+
+#[test]
+fn test_binary_setup_encrypted_store() {
+    let server = FakeServer::start();
+    let home = TestHome::empty("setup-encrypted");
+    let output = setup_command(&home, &server)
+        .env("ELASTICNOW_PASSPHRASE", "correct horse")
+        .args(["--sn-password", "hunter2", "--bin", "Linux"])
+        .args(["--credential-store", "encrypted"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let config = std::fs::read_to_string(home.config_file("config.toml")).unwrap();
+    assert!(!config.contains("hunter2") && !config.contains(VALID_SESSION));
+    let secrets = std::fs::read(home.config_file("secrets.enc")).unwrap();
+    assert!(!String::from_utf8_lossy(&secrets).contains("hunter2"));
+
+    let report = |passphrase: &str| {
+        home.command()
+            .env("ELASTICNOW_PASSPHRASE", passphrase)
+            .args(["report", "--format", "json"])
+            .args(["--since", "2024-06-24", "--until", "2024-06-30"])
+            .output()
+            .unwrap()
+    };
+    assert_eq!(report("wrong").status.code(), Some(2));
+    assert!(server.requests().is_empty());
+    let output = report("correct horse");
+    assert!(output.status.success(), "{:?}", output);
+    let lookup = &server.requests_to("GET", "/api/now/table/task_time_worked")[0];
+    assert_eq!(lookup.authorization, basic_auth("jdoe", "hunter2"));
+}
+
+#[test]
+fn test_binary_setup_keeps_oauth() {
+    let server = FakeServer::start();
+    let home = TestHome::empty("setup-oauth");
+    let setup = |args: &[&str]| {
+        setup_command(&home, &server)
+            .args(["--sn-password", SN_PASSWORD, "--bin", "Linux"])
+            .args(["--credential-store", "config"])
+            .args(args)
+            .output()
+            .unwrap()
+    };
+    assert_eq!(setup(&["--auth", "oauth"]).status.code(), Some(1));
+    let output = setup(&["--auth", "oauth", "--oauth-client-id", OAUTH_CLIENT_ID]);
+    assert!(output.status.success(), "{:?}", output);
+    let output = setup(&["--oauth-client-secret", "secret"]);
+    assert!(output.status.success(), "{:?}", output);
+    // Running setup again without auth flags keeps the OAuth application
+    let output = setup(&[]);
+    assert!(output.status.success(), "{:?}", output);
+    let config = std::fs::read_to_string(home.config_file("config.toml")).unwrap();
+    assert!(config.contains("auth = \"oauth\""), "{}", config);
+    assert!(
+        config.contains(&format!("client_id = \"{}\"", OAUTH_CLIENT_ID)),
+        "{}",
+        config
+    );
+    assert!(config.contains("client_secret = \"secret\""), "{}", config);
+
+    let output = setup(&["--auth", "basic"]);
+    assert!(output.status.success(), "{:?}", output);
+    let config = std::fs::read_to_string(home.config_file("config.toml")).unwrap();
+    assert!(config.contains("auth = \"basic\""), "{}", config);
+}
+
+#[tokio::test]
+async fn test_servicenow_oauth() {
+    let server = FakeServer::start();
+    let home = TestHome::new("oauth", &server);
+    let cache_path = home.config_file("oauth_token.toml");
+    let oauth_client = || {
+        servicenow(&server).with_oauth(OAuth::new(
+            OAUTH_CLIENT_ID,
+            "secret",
+            Some(cache_path.clone()),
+        ))
+    };
+
+    let sn_client = oauth_client();
+    assert_eq!(sn_client.get_user_group("jdoe").await.unwrap(), "Linux");
+    sn_client.get_all_tickets_in_bin("Linux").await.unwrap();
+    let grants = server.requests_to("POST", "/oauth_token.do");
+    assert_eq!(grants.len(), 1);
+    assert!(grants[0].body.contains("grant_type=password"));
+    assert!(server.requests_to("GET", "/api/now/table/task")[0]
+        .authorization
+        .ends_with("access-1"));
+    let cache = std::fs::read_to_string(&cache_path).unwrap();
+    assert!(cache.contains("access-1") && cache.contains("refresh-1"));
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let metadata = std::fs::metadata(&cache_path).unwrap();
+        assert_eq!(metadata.permissions().mode() & 0o777, 0o600);
+    }
+
+    // A new client reuses the cached token, and refreshes it once it is rejected
+    let sn_client = oauth_client();
+    server.revoke_access_tokens();
+    assert_eq!(sn_client.get_user_group("jdoe").await.unwrap(), "Linux");
+    let grants = server.requests_to("POST", "/oauth_token.do");
+    assert_eq!(grants.len(), 2);
+    assert!(grants[1].body.contains("grant_type=refresh_token"));
+    assert!(grants[1].body.contains("refresh_token=refresh-1"));
+    let lookups = server.requests_to("GET", "/api/now/table/sys_user");
+    assert_eq!(lookups.len(), 3);
+    assert!(lookups[1].authorization.ends_with("access-1"));
+    assert!(lookups[2].authorization.ends_with("access-2"));
+
+    let wrong_password = ServiceNow::new("jdoe", "wrong", &server.url).with_oauth(OAuth::new(
+        OAUTH_CLIENT_ID,
+        "secret",
+        None,
+    ));
+    assert!(wrong_password
+        .get_user_group("jdoe")
+        .await
+        .unwrap_err()
+        .is_auth());
+}
+
+#[test]
+fn test_binary_report_with_oauth() {
+    let server = FakeServer::start();
+    let home = TestHome::new("report-oauth", &server);
+    let config = std::fs::read_to_string(home.config_file("config.toml")).unwrap();
+    std::fs::write(
+        home.config_file("config.toml"),
+        format!(
+            "{}auth = \"oauth\"\n[oauth]\nclient_id = \"{}\"\nclient_secret = \"secret\"\n",
+            config, OAUTH_CLIENT_ID
+        ),
+    )
+    .unwrap();
+    let output = home
+        .command()
+        .args(["report", "--format", "json"])
+        .args(["--since", "2024-06-24", "--until", "2024-06-30"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(server.requests_to("POST", "/oauth_token.do").len(), 1);
+    let lookup = &server.requests_to("GET", "/api/now/table/task_time_worked")[0];
+    assert_eq!(lookup.authorization, "Bearer access-1");
+    assert!(home.config_file("oauth_token.toml").exists());
+}
+
+#[test]
+fn test_binary_profiles() {
+    let prod = FakeServer::start();
+    let dev = FakeServer::start();
+    let home = TestHome::new("profiles", &prod);
+    let output = setup_command(&home, &dev)
+        .args(["--profile", "dev", "--credential-store", "config"])
+        .args(["--sn-password", "dev-password", "--bin", "Linux"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let config = std::fs::read_to_string(home.config_file("config.toml")).unwrap();
+    assert!(config.contains("[profiles.default]") && config.contains("[profiles.dev]"));
+
+    let report = |profile: Option<&str>| {
+        let mut command = home.command();
+        if let Some(profile) = profile {
+            command.env("ELASTICNOW_PROFILE", profile);
+        }
+        command
+            .args(["report", "--format", "json"])
+            .args(["--since", "2024-06-24", "--until", "2024-06-30"])
+            .output()
+            .unwrap()
+    };
+    assert!(report(None).status.success());
+    assert_eq!(
+        prod.requests_to("GET", "/api/now/table/task_time_worked")
+            .len(),
+        1
+    );
+    assert!(dev
+        .requests_to("GET", "/api/now/table/task_time_worked")
+        .is_empty());
+    assert!(report(Some("dev")).status.success());
+    let lookup = &dev.requests_to("GET", "/api/now/table/task_time_worked")[0];
+    assert_eq!(lookup.authorization, basic_auth("jdoe", "dev-password"));
+    assert_eq!(report(Some("staging")).status.code(), Some(2));
+    // Profile names become file names, so paths are rejected before anything is read or written
+    let output = report(Some("../../escaped"));
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("invalid profile name"));
+
+    // Queued entries are only synced to the profile they were queued for
+    std::fs::write(
+        home.config_file("queue.dev.toml"),
+        "[[entries]]\nsys_id = \"sys1\"\ntime_worked = \"1h\"\ncomment = \"dev\"\ndate = \"2024-06-21\"\n",
+    )
+    .unwrap();
+    assert!(home
+        .command()
+        .arg("sync")
+        .output()
+        .unwrap()
+        .status
+        .success());
+    assert!(prod
+        .requests_to("POST", "/api/now/table/task_time_worked")
+        .is_empty());
+    let output = home
+        .command()
+        .args(["--profile", "dev", "sync"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(
+        dev.requests_to("POST", "/api/now/table/task_time_worked")
+            .len(),
+        1
+    );
+}
+
+#[test]
+fn test_binary_config_commands() {
+    let server = FakeServer::start();
+    let home = TestHome::new("config", &server);
+    let config = |args: &[&str]| home.command().arg("config").args(args).output().unwrap();
+
+    let output = config(&["show"]);
+    assert!(output.status.success(), "{:?}", output);
+    let shown = String::from_utf8_lossy(&output.stdout);
+    assert!(shown.contains("sn_password = \"********\"") && shown.contains("bin = \"Linux\""));
+    assert!(!shown.contains(SN_PASSWORD) && !shown.contains(VALID_SESSION));
+
+    let output = config(&["set", "bin=Windows", "retry.max_attempts=2"]);
+    assert!(output.status.success(), "{:?}", output);
+    assert!(!config(&["set", "colour=blue"]).status.success());
+    assert!(!config(&["set", "retry.max_attempts=many"]).status.success());
+    let output = config(&["set", "sn_password=letmein"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("run elasticnow setup"));
+    let get = |key: &str| String::from_utf8(config(&["get", key]).stdout).unwrap();
+    assert_eq!(get("bin"), "Windows\n");
+    assert_eq!(get("retry.max_attempts"), "2\n");
+    assert_eq!(get("sn_password"), format!("{}\n", SN_PASSWORD));
+    assert_eq!(config(&["get", "daily_target"]).status.code(), Some(1));
+
+    let output = config(&["validate"]);
+    assert_eq!(output.status.code(), Some(1), "{:?}", output);
+    let validated = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        validated.contains("Bin Windows: Not found"),
+        "{}",
+        validated
+    );
+    assert!(config(&["set", "bin=Linux"]).status.success());
+    let output = config(&["validate"]);
+    assert!(output.status.success(), "{:?}", output);
+
+    let output = config(&["path"]);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout).trim(),
+        home.config_file("config.toml").display().to_string()
+    );
+}
+
+#[test]
+fn test_binary_config_switch_credential_store() {
+    let server = FakeServer::start();
+    let home = TestHome::new("config-store", &server);
+    let config = |args: &[&str]| {
+        home.command()
+            .env("ELASTICNOW_PASSPHRASE", "correct horse")
+            .arg("config")
+            .args(args)
+            .output()
+            .unwrap()
+    };
+    let authorization = || {
+        let output = home
+            .command()
+            .env("ELASTICNOW_PASSPHRASE", "correct horse")
+            .args(["report", "--format", "json"])
+            .args(["--since", "2024-06-24", "--until", "2024-06-30"])
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{:?}", output);
+        server
+            .requests_to("GET", "/api/now/table/task_time_worked")
+            .pop()
+            .unwrap()
+            .authorization
+    };
+
+    let output = config(&[
+        "set",
+        "password_command=echo s3cret",
+        "credential_store=command",
+    ]);
+    assert!(output.status.success(), "{:?}", output);
+    let stored = std::fs::read_to_string(home.config_file("config.toml")).unwrap();
+    assert!(!stored.contains("sn_password"), "{}", stored);
+    assert_eq!(authorization(), basic_auth("jdoe", "s3cret"));
+
+    // The password comes from the old store, here the command
+    let output = config(&["set", "credential_store=config"]);
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(
+        String::from_utf8(config(&["get", "sn_password"]).stdout).unwrap(),
+        "s3cret\n"
+    );
+    assert_eq!(authorization(), basic_auth("jdoe", "s3cret"));
+
+    let output = config(&["set", "credential_store=encrypted"]);
+    assert!(output.status.success(), "{:?}", output);
+    let stored = std::fs::read_to_string(home.config_file("config.toml")).unwrap();
+    assert!(!stored.contains("sn_password"), "{}", stored);
+    assert_eq!(authorization(), basic_auth("jdoe", "s3cret"));
+
+    // Without SN_PASSWORD the env store has nothing to move
+    let output = config(&["set", "credential_store=env"]);
+    assert!(output.status.success(), "{:?}", output);
+    let output = config(&["set", "credential_store=config"]);
+    assert_eq!(output.status.code(), Some(2), "{:?}", output);
+    let output = home
+        .command()
+        .env("SN_PASSWORD", "")
+        .args(["config", "set", "credential_store=config"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1), "{:?}", output);
+    let output = home
+        .command()
+        .env("SN_PASSWORD", "from-env")
+        .args(["config", "set", "credential_store=config"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(authorization(), basic_auth("jdoe", "from-env"));
+}
+
+#[test]
+fn test_binary_config_errors_and_migration() {
+    let server = FakeServer::start();
+    let home = TestHome::new("config-migration", &server);
+    let legacy = std::fs::read_to_string(home.config_file("config.toml")).unwrap();
+    let report = || {
+        home.command()
+            .args(["report", "--format", "json"])
+            .args(["--since", "2024-06-24", "--until", "2024-06-30"])
+            .output()
+            .unwrap()
+    };
+    let output = report();
+    assert!(output.status.success(), "{:?}", output);
+    let migrated = std::fs::read_to_string(home.config_file("config.toml")).unwrap();
+    assert!(migrated.starts_with("version = 2"), "{}", migrated);
+    assert!(migrated.contains("[profiles.default]"));
+    let backup = std::fs::read_to_string(home.config_file("config.toml.bak")).unwrap();
+    assert_eq!(backup, legacy);
+
+    let requests = server.requests().len();
+    std::fs::write(
+        home.config_file("config.toml"),
+        migrated.replace("sn_username", "sn_user"),
+    )
+    .unwrap();
+    let output = report();
+    assert_eq!(output.status.code(), Some(2));
+    // Errors are logged to stdout
+    let logged = String::from_utf8_lossy(&output.stdout);
+    assert!(logged.contains("unknown field `sn_user`"), "{}", logged);
+    assert!(logged.contains("line "), "{}", logged);
+    assert_eq!(server.requests().len(), requests);
+}
+
+#[test]
+fn test_binary_no_ticket_categories() {
+    let server = FakeServer::start();
+    let home = TestHome::new("categories", &server);
+    let track = |category: &str| {
+        home.command()
+            .args(["timetrack", "--no-tkt", "--category", category])
+            .args(["-c", "meetings", "-t", "30m"])
+            .output()
+            .unwrap()
+    };
+    let output = track("University Events");
+    assert!(output.status.success(), "{:?}", output);
+    let output = track("on_call");
+    assert!(output.status.success(), "{:?}", output);
+    let output = track("gardening");
+    assert_eq!(output.status.code(), Some(1));
+    let posted = server.requests_to("POST", "/api/now/table/task_time_worked");
+    assert_eq!(posted.len(), 2);
+    assert_eq!(posted[0].json()["u_category"], "univ_events");
+    assert_eq!(posted[1].json()["u_category"], "on_call");
+    // Fetched once, then read from the cache
+    let lookups = server.requests_to("GET", "/api/now/table/sys_choice");
+    assert_eq!(lookups.len(), 1);
+    assert!(lookups[0].url.contains("element=u_category"));
+    assert!(home.config_file("categories.toml").exists());
+}
+
+#[test]
+fn test_binary_non_interactive_ticket_selection() {
+    let server = FakeServer::start();
+    let home = TestHome::new("ticket-selection", &server);
+    let track = |args: &[&str]| {
+        home.command()
+            .arg("timetrack")
+            .args(args)
+            .args(["-c", "scripted", "-t", "15m"])
+            .output()
+            .unwrap()
+    };
+    let output = track(&["--ticket", "RITM0000002"]);
+    assert!(output.status.success(), "{:?}", output);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("task.do?sys_id=sys2"));
+    assert_eq!(track(&["--ticket", "RITM9999999"]).status.code(), Some(1));
+    let output = track(&["--sys-id", "sys1"]);
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(track(&["--sys-id", "sys9"]).status.code(), Some(1));
+    let output = track(&["--all", "--pick-first"]);
+    assert!(output.status.success(), "{:?}", output);
+    let output = track(&["--search", "patch", "--pick-first"]);
+    assert!(output.status.success(), "{:?}", output);
+    assert!(!track(&["--ticket", "RITM0000002", "--search", "patch"])
+        .status
+        .success());
+
+    let posted: Vec<String> = server
+        .requests_to("POST", "/api/now/table/task_time_worked")
+        .iter()
+        .map(|r| r.json()["task"].as_str().unwrap().to_string())
+        .collect();
+    assert_eq!(posted, ["sys2", "sys1", "sys1", "sys1"]);
+    let lookups = server.requests_to("GET", "/api/now/table/task");
+    assert!(lookups[0].url.contains("number=RITM0000002"));
+
+    // An expired session is not renewed through the browser when nobody is there to log in
+    let config = std::fs::read_to_string(home.config_file("config.toml")).unwrap();
+    std::fs::write(
+        home.config_file("config.toml"),
+        config.replace(VALID_SESSION, "expired-session"),
+    )
+    .unwrap();
+    let output = track(&["--search", "patch", "--pick-first"]);
+    assert_eq!(output.status.code(), Some(1), "{:?}", output);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Log in again"));
+}
+
+#[test]
+fn test_binary_recent_and_favourite_tickets() {
+    let server = FakeServer::start();
+    let home = TestHome::new("history", &server);
+    let run = |args: &[&str]| home.command().args(args).output().unwrap();
+    let track = |args: &[&str]| {
+        let mut command = vec!["timetrack", "-c", "scripted", "-t", "15m"];
+        command.extend(args);
+        run(&command)
+    };
+    // Nothing to pick from before time was tracked
+    assert_eq!(track(&["--recent"]).status.code(), Some(1));
+    for ticket in ["RITM0000002", "RITM0000002"] {
+        let output = track(&["--ticket", ticket]);
+        assert!(output.status.success(), "{:?}", output);
+    }
+    let history = std::fs::read_to_string(home.config_file("history.toml")).unwrap();
+    assert!(history.contains("sys_id = \"sys2\""), "{}", history);
+    assert!(history.contains("count = 2"), "{}", history);
+
+    let output = run(&["fav", "add", "RITM0000001"]);
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(run(&["fav", "add", "RITM9999999"]).status.code(), Some(1));
+    let output = run(&["fav", "list"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("★ RITM0000001: Patch web servers"),
+        "{}",
+        stdout
+    );
+    assert!(
+        stdout.contains("↺ RITM0000002: Renew certificates"),
+        "{}",
+        stdout
+    );
+    assert!(stdout.contains("Used 2 times"), "{}", stdout);
+
+    assert!(run(&["fav", "rm", "RITM0000001"]).status.success());
+    assert_eq!(run(&["fav", "rm", "RITM0000001"]).status.code(), Some(1));
+    let output = run(&["fav", "list"]);
+    assert!(!String::from_utf8_lossy(&output.stdout).contains("RITM0000001"));
+    assert!(!track(&["--recent", "--pick-first"]).status.success());
+}
+
+#[test]
+fn test_binary_split_time() {
+    let server = FakeServer::start();
+    let home = TestHome::new("split", &server);
+    let run = |args: &[&str]| {
+        home.command()
+            .args(["timetrack", "-c", "planning", "-d", "2024-06-24"])
+            .args(args)
+            .output()
+            .unwrap()
+    };
+    let output = run(&[
+        "-t",
+        "4h",
+        "--split",
+        "RITM0000001=50%,RITM0000002=25%,clerical=25%",
+    ]);
+    assert!(output.status.success(), "{:?}", output);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("RITM0000001: Patch web servers"),
+        "{}",
+        stdout
+    );
+    assert!(stdout.contains("Clerical"), "{}", stdout);
+    let posted: Vec<(String, String, String)> = server
+        .requests_to("POST", "/api/now/table/task_time_worked")
+        .iter()
+        .map(|r| {
+            let json = r.json();
+            let field = |name: &str| json[name].as_str().unwrap_or_default().to_string();
+            (field("task"), field("u_category"), field("time_worked"))
+        })
+        .collect();
+    assert_eq!(
+        posted,
+        [
+            ("sys1".into(), "".into(), "1970-01-01+02:00:00".into()),
+            ("sys2".into(), "".into(), "1970-01-01+01:00:00".into()),
+            ("".into(), "clerical".into(), "1970-01-01+01:00:00".into()),
+        ]
+    );
+    let history = std::fs::read_to_string(home.config_file("history.toml")).unwrap();
+    assert!(history.contains("RITM0000002"), "{}", history);
+
+    // Nothing is posted when the split does not add up or a target is unknown
+    assert_eq!(
+        run(&["-t", "2h", "--split", "RITM0000001=1h,clerical=30m"])
+            .status
+            .code(),
+        Some(1)
+    );
+    assert_eq!(
+        run(&["-t", "2h", "--split", "RITM9999999=1h,clerical=1h"])
+            .status
+            .code(),
+        Some(1)
+    );
+    assert!(
+        !run(&["-t", "2h", "--split", "RITM0000001=1h,clerical=1h", "--all"])
+            .status
+            .success()
+    );
+    assert_eq!(
+        server
+            .requests_to("POST", "/api/now/table/task_time_worked")
+            .len(),
+        3
+    );
+
+    // The ticket lookup and the first post succeed, the rejected second post rolls the first back
+    server.fail_after(2, 400);
+    let output = run(&["-t", "2h", "--split", "RITM0000001=1h,clerical=1h"]);
+    assert_eq!(output.status.code(), Some(2), "{:?}", output);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Nothing was tracked"));
+    let deleted = server.requests_to("DELETE", "/api/now/table/task_time_worked/time1");
+    assert_eq!(deleted.len(), 1);
+
+    // A 5xx may come after the record was created, so it is not reported as nothing tracked
+    server.fail_after(2, 503);
+    let output = run(&["-t", "2h", "--split", "RITM0000001=1h,clerical=1h"]);
+    assert_eq!(output.status.code(), Some(2), "{:?}", output);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(!stdout.contains("Nothing was tracked"), "{}", stdout);
+    assert!(
+        stdout.contains("may have tracked the time anyway"),
+        "{}",
+        stdout
+    );
+    let deleted = server.requests_to("DELETE", "/api/now/table/task_time_worked/time1");
+    assert_eq!(deleted.len(), 2);
+}
+
+#[test]
+fn test_binary_import_timesheet() {
+    let server = FakeServer::start();
+    let home = TestHome::new("import", &server);
+    let run = |args: &[&str]| home.command().arg("import").args(args).output().unwrap();
+    let posts = || {
+        server
+            .requests_to("POST", "/api/now/table/task_time_worked")
+            .len()
+    };
+    let sheet = home.path.join("timesheet.csv");
+    std::fs::write(
+        &sheet,
+        "date,ticket,duration,comment\n\
+         2024-06-24,RITM0000001,1h30m,Patching\n\
+         2024-06-24,clerical,30m,Email\n\
+         2024-06-25,RITM0000002,2h,Renewals\n",
+    )
+    .unwrap();
+    let sheet = sheet.to_str().unwrap();
+
+    let output = run(&[sheet, "--dry-run"]);
+    assert!(output.status.success(), "{:?}", output);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("3 entries"), "{}", stdout);
+    assert!(stdout.contains("2024-06-24"), "{}", stdout);
+    // Without a terminal to confirm in, --yes is required
+    assert_eq!(run(&[sheet]).status.code(), Some(1));
+    assert_eq!(posts(), 0);
+
+    let output = run(&[sheet, "--yes"]);
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(posts(), 3);
+    let results = std::fs::read_to_string(home.path.join("timesheet.results.csv")).unwrap();
+    let lines: Vec<&str> = results.lines().collect();
+    assert_eq!(
+        lines[0],
+        "row,date,target,duration,comment,status,sys_id,error"
+    );
+    assert_eq!(
+        lines[1],
+        "1,2024-06-24,RITM0000001,1h30m,Patching,tracked,time1,"
+    );
+    assert_eq!(lines.len(), 4);
+
+    // A rejected post is recorded and the remaining rows are still posted
+    server.fail_after(3, 400);
+    let output = run(&[sheet, "--yes"]);
+    assert_eq!(output.status.code(), Some(2), "{:?}", output);
+    assert_eq!(posts(), 6);
+    let results = std::fs::read_to_string(home.path.join("timesheet.results.csv")).unwrap();
+    let statuses: Vec<&str> = results
+        .lines()
+        .skip(1)
+        .map(|l| l.split(',').nth(5).unwrap())
+        .collect();
+    assert_eq!(statuses, ["tracked", "failed", "tracked"]);
+    assert!(!home.path.join("timesheet.results.csv.tmp").exists());
+
+    // A 5xx may come after the record was created, so the row is not marked as failed
+    server.fail_after(3, 503);
+    let output = run(&[sheet, "--yes"]);
+    assert_eq!(output.status.code(), Some(2), "{:?}", output);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("may have tracked the time anyway"));
+    assert_eq!(posts(), 9);
+    let results = std::fs::read_to_string(home.path.join("timesheet.results.csv")).unwrap();
+    let statuses: Vec<&str> = results
+        .lines()
+        .skip(1)
+        .map(|l| l.split(',').nth(5).unwrap())
+        .collect();
+    assert_eq!(statuses, ["tracked", "unknown", "tracked"]);
+
+    // One invalid row stops the whole import and is reported in the results
+    let json = home.path.join("timesheet.json");
+    std::fs::write(
+        &json,
+        r#"[
+            {"date": "2024-06-26", "category": "clerical", "duration": "1h", "comment": "Email"},
+            {"date": "2024-06-26", "ticket": "RITM9999999", "duration": "1h", "comment": "Typo"}
+        ]"#,
+    )
+    .unwrap();
+    let output = run(&[json.to_str().unwrap(), "--yes"]);
+    assert_eq!(output.status.code(), Some(1), "{:?}", output);
+    assert_eq!(posts(), 9);
+    let results: serde_json::Value = serde_json::from_str(
+        &std::fs::read_to_string(home.path.join("timesheet.results.json")).unwrap(),
+    )
+    .unwrap();
+    assert_eq!(results[0]["status"], "skipped");
+    assert_eq!(results[1]["status"], "invalid");
+    assert_eq!(results[1]["target"], "RITM9999999");
+}
@@ -0,0 +1,506 @@
+use elasticnow::cli::args::ReportBy;
+use elasticnow::cli::categories::default_categories;
+use elasticnow::cli::create::{create_std_chg, create_ticket};
+use elasticnow::cli::history::History;
+use elasticnow::cli::queue::QueuedEntry;
+use elasticnow::cli::report::summarize_time_worked;
+use elasticnow::cli::timetrack::{
+    find_ticket, record_ticket, resolve_category, resolve_split, sync_entries, track_split,
+    track_time, TicketRef, TimeEntry, TimeTarget,
+};
+use elasticnow::elasticnow::backend::{TicketBackend, TicketSearch};
+use elasticnow::elasticnow::error::Error;
+use elasticnow::elasticnow::fake::{FakeBackend, FakeTicket};
+use elasticnow::elasticnow::servicenow_structs::SysIdResult;
+use std::collections::HashMap;
+
+fn backend() -> FakeBackend {
+    FakeBackend::new("jdoe")
+        .with_ticket(FakeTicket {
+            sys_id: "sys1".to_string(),
+            number: "RITM0000001".to_string(),
+            short_description: "Patch web servers".to_string(),
+            assignment_group: "Linux".to_string(),
+            cost_center: Some("IT Infrastructure".to_string()),
+        })
+        .with_ticket(FakeTicket {
+            sys_id: "sys2".to_string(),
+            number: "RITM0000002".to_string(),
+            short_description: "Renew certificates".to_string(),
+            assignment_group: "Linux".to_string(),
+            cost_center: Some("Security".to_string()),
+        })
+}
+
+fn entry(target: TimeTarget, time_worked: &str, date: &str) -> TimeEntry {
+    TimeEntry {
+        target,
+        time_worked: time_worked.to_string(),
+        comment: "work".to_string(),
+        date: Some(date.to_string()),
+    }
+}
+
+#[tokio::test]
+async fn test_timetrack_search_then_report_by_ticket() {
+    let backend = backend();
+    let results = backend.get_keyword_tickets("patch", "Linux").await.unwrap();
+    assert_eq!(results.len(), 1);
+    let sys_id = results[0].source.id.clone();
+    track_time(
+        &backend,
+        &entry(TimeTarget::Ticket(sys_id), "1h30m", "2024-06-24"),
+    )
+    .await
+    .unwrap();
+    track_time(
+        &backend,
+        &entry(TimeTarget::Ticket("sys2".to_string()), "30m", "2024-06-25"),
+    )
+    .await
+    .unwrap();
+
+    let summary = summarize_time_worked(
+        &backend,
+        "jdoe",
+        "2024-06-24",
+        "2024-06-30",
+        ReportBy::Ticket,
+        &default_categories(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(summary.total, 7200);
+    assert_eq!(
+        summary.time_worked,
+        HashMap::from([
+            ("RITM0000001: Patch web servers".to_string(), 5400),
+            ("RITM0000002: Renew certificates".to_string(), 1800),
+        ])
+    );
+}
+
+#[tokio::test]
+async fn test_report_by_cost_center_category_and_day() {
+    let backend = backend();
+    track_time(
+        &backend,
+        &entry(TimeTarget::Ticket("sys1".to_string()), "2h", "2024-06-24"),
+    )
+    .await
+    .unwrap();
+    track_time(
+        &backend,
+        &entry(
+            TimeTarget::Category("clerical".to_string()),
+            "1h",
+            "2024-06-25",
+        ),
+    )
+    .await
+    .unwrap();
+    // Outside of the reported range
+    track_time(
+        &backend,
+        &entry(TimeTarget::Ticket("sys2".to_string()), "4h", "2024-07-02"),
+    )
+    .await
+    .unwrap();
+
+    let by_cost_center = summarize_time_worked(
+        &backend,
+        "jdoe",
+        "2024-06-24",
+        "2024-06-30",
+        ReportBy::CostCenter,
+        &default_categories(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(by_cost_center.total, 10800);
+    assert_eq!(
+        by_cost_center.time_worked,
+        HashMap::from([
+            ("IT Infrastructure".to_string(), 7200),
+            ("Clerical".to_string(), 3600),
+        ])
+    );
+
+    let by_category = summarize_time_worked(
+        &backend,
+        "jdoe",
+        "2024-06-24",
+        "2024-06-30",
+        ReportBy::Category,
+        &default_categories(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        by_category.time_worked,
+        HashMap::from([
+            ("Ticket work".to_string(), 7200),
+            ("Clerical".to_string(), 3600),
+        ])
+    );
+
+    let by_day = summarize_time_worked(
+        &backend,
+        "jdoe",
+        "2024-06-24",
+        "2024-06-30",
+        ReportBy::Day,
+        &default_categories(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        by_day.time_worked,
+        HashMap::from([
+            ("2024-06-24".to_string(), 7200),
+            ("2024-06-25".to_string(), 3600),
+        ])
+    );
+}
+
+#[tokio::test]
+async fn test_offline_entries_sync_later() {
+    let backend = backend();
+    backend.set_offline(true);
+    let time_entry = entry(TimeTarget::Ticket("sys1".to_string()), "45m", "2024-06-21");
+    let err = track_time(&backend, &time_entry).await.unwrap_err();
+    assert!(err.is_transient() && err.is_unsent());
+    let queued = QueuedEntry::from_time_entry(&time_entry, "2024-06-21".to_string(), None);
+
+    let (synced, failed, uncertain) = sync_entries(&backend, vec![queued.clone()]).await;
+    assert!(synced.is_empty() && uncertain.is_empty());
+    assert!(failed[0].last_error.is_some());
+
+    backend.set_offline(false);
+    let (synced, failed, uncertain) = sync_entries(&backend, failed).await;
+    assert_eq!(synced.len(), 1);
+    assert!(failed.is_empty() && uncertain.is_empty());
+    let summary = summarize_time_worked(
+        &backend,
+        "jdoe",
+        "2024-06-21",
+        "2024-06-21",
+        ReportBy::Day,
+        &default_categories(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(summary.total, 2700);
+}
+
+#[tokio::test]
+async fn test_invalid_time_is_not_tracked() {
+    let backend = backend();
+    let err = track_time(
+        &backend,
+        &entry(TimeTarget::Ticket("sys1".to_string()), "soon", "2024-06-24"),
+    )
+    .await
+    .unwrap_err();
+    assert!(!err.is_transient());
+    assert!(backend.time_entries.lock().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_new_ticket_and_std_chg() {
+    let mut backend = backend();
+    backend.std_chg_templates = vec![
+        SysIdResult {
+            sys_id: "tmpl1".to_string(),
+            sys_name: Some("Patch Linux servers".to_string()),
+        },
+        SysIdResult {
+            sys_id: "tmpl2".to_string(),
+            sys_name: Some("Patch Windows servers".to_string()),
+        },
+    ];
+    assert!(matches!(
+        create_ticket(&backend, "Linux", "  ").await,
+        Err(Error::Validation(_))
+    ));
+    let sys_id = create_ticket(&backend, "Linux", " New work ")
+        .await
+        .unwrap();
+    assert_eq!(
+        backend.get_all_tickets_in_bin("Linux").await.unwrap().len(),
+        3
+    );
+    assert_eq!(
+        backend.get_tasks_by_sys_id(&[sys_id]).await.unwrap()[0].short_description,
+        "New work"
+    );
+
+    let chg = create_std_chg(&backend, "Patch", "Linux", None, |templates| {
+        assert_eq!(templates.len(), 2);
+        templates[1].sys_id.clone()
+    })
+    .await
+    .unwrap();
+    assert_eq!(chg, "fake_chg_1");
+    // A template id skips the search
+    let chg = create_std_chg(&backend, "", "Windows", Some("tmpl1".to_string()), |_| {
+        panic!("no template to choose")
+    })
+    .await
+    .unwrap();
+    assert_eq!(chg, "fake_chg_2");
+    assert!(matches!(
+        create_std_chg(&backend, "Solaris", "Linux", None, |_| String::new()).await,
+        Err(Error::NotFound { .. })
+    ));
+    assert_eq!(
+        *backend.changes.lock().unwrap(),
+        vec![
+            ("tmpl2".to_string(), "Linux".to_string()),
+            ("tmpl1".to_string(), "Windows".to_string())
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_categories_are_cached_and_overridden() {
+    use elasticnow::cli::categories::{find_category, load_categories, CategoryCache};
+    use elasticnow::elasticnow::servicenow_structs::Choice;
+    let choice = |value: &str, label: &str| Choice {
+        value: value.to_string(),
+        label: label.to_string(),
+        hint: String::new(),
+    };
+    let cache_path =
+        std::env::temp_dir().join(format!("elasticnow-categories-{}.toml", std::process::id()));
+    let _ = std::fs::remove_file(&cache_path);
+    let backend = FakeBackend {
+        categories: vec![choice("on_call", "On Call")],
+        ..backend()
+    };
+
+    backend.set_offline(true);
+    assert_eq!(
+        load_categories(&backend, &[], &cache_path).await,
+        default_categories()
+    );
+    backend.set_offline(false);
+    let fetched = load_categories(&backend, &[], &cache_path).await;
+    assert_eq!(fetched, vec![choice("on_call", "On Call")]);
+    // A stale cache is still better than the defaults when ServiceNow is unreachable
+    let mut cache = CategoryCache::from_toml_file(&cache_path).unwrap();
+    cache.fetched_at = 0;
+    cache.to_toml_file(&cache_path).unwrap();
+    backend.set_offline(true);
+    assert_eq!(load_categories(&backend, &[], &cache_path).await, fetched);
+
+    let overrides = vec![choice("lunch", "Lunch")];
+    assert_eq!(
+        load_categories(&backend, &overrides, &cache_path).await,
+        overrides
+    );
+    assert_eq!(find_category(&fetched, "on call").unwrap().value, "on_call");
+    assert!(find_category(&fetched, "clerical").is_none());
+    let _ = std::fs::remove_file(&cache_path);
+}
+
+#[tokio::test]
+async fn test_split_is_all_or_nothing() {
+    let backend = backend();
+    let entries = vec![
+        entry(TimeTarget::Ticket("sys1".to_string()), "2h", "2024-06-24"),
+        entry(TimeTarget::Ticket("sys2".to_string()), "1h", "2024-06-24"),
+        entry(
+            TimeTarget::Category("clerical".to_string()),
+            "1h",
+            "2024-06-24",
+        ),
+    ];
+    let created = track_split(&backend, &entries).await.unwrap();
+    assert_eq!(created.len(), 3);
+    assert_eq!(backend.time_entries.lock().unwrap().len(), 3);
+
+    // The third post is rejected, so the first two are deleted again
+    backend.set_time_entry_limit(Some(5));
+    let failure = track_split(&backend, &entries).await.unwrap_err();
+    assert_eq!(failure.index, 2);
+    assert!(failure.not_rolled_back.is_empty());
+    let remaining: Vec<String> = backend
+        .time_entries
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|e| e.sys_id.clone())
+        .collect();
+    assert_eq!(remaining, created);
+}
+
+#[tokio::test]
+async fn test_ticket_is_found_then_recorded_in_history() {
+    let backend = backend();
+    let by_sys_id = find_ticket(&backend, &TicketRef::SysId("sys2".to_string()))
+        .await
+        .unwrap();
+    assert_eq!(by_sys_id.number, "RITM0000002");
+    let by_number = find_ticket(&backend, &TicketRef::Number("RITM0000001".to_string()))
+        .await
+        .unwrap();
+    assert_eq!(by_number.sys_id, "sys1");
+    for missing in [
+        TicketRef::SysId("sys9".to_string()),
+        TicketRef::Number("RITM9999999".to_string()),
+    ] {
+        assert!(matches!(
+            find_ticket(&backend, &missing).await,
+            Err(Error::NotFound { .. })
+        ));
+    }
+
+    let mut history = History::default();
+    assert!(record_ticket(&backend, &mut history, "sys1", Some(by_number)).await);
+    // Only the sys_id is known for a new ticket, so it is looked up
+    assert!(record_ticket(&backend, &mut history, "sys2", None).await);
+    assert!(!record_ticket(&backend, &mut history, "sys9", None).await);
+    let numbers: Vec<&str> = history.tickets.iter().map(|t| t.number.as_str()).collect();
+    assert_eq!(numbers.len(), 2);
+    assert!(numbers.contains(&"RITM0000001") && numbers.contains(&"RITM0000002"));
+
+    assert_eq!(
+        resolve_category(&default_categories(), "CLERICAL").unwrap(),
+        "clerical"
+    );
+    assert!(matches!(
+        resolve_category(&default_categories(), "lunch"),
+        Err(Error::Validation(_))
+    ));
+}
+
+#[tokio::test]
+async fn test_split_targets_are_resolved_before_tracking() {
+    let backend = backend();
+    let resolved = resolve_split(
+        &backend,
+        &default_categories(),
+        "ritm0000001=50%,clerical=50%",
+        "3h",
+        "work",
+        Some("2024-06-24"),
+    )
+    .await
+    .unwrap();
+    let (entries, targets): (Vec<_>, Vec<_>) = resolved.into_iter().unzip();
+    assert_eq!(
+        entries,
+        vec![
+            entry(
+                TimeTarget::Ticket("sys1".to_string()),
+                "1h30m",
+                "2024-06-24"
+            ),
+            entry(
+                TimeTarget::Category("clerical".to_string()),
+                "1h30m",
+                "2024-06-24"
+            ),
+        ]
+    );
+    assert_eq!(targets[0].label, "RITM0000001: Patch web servers");
+    assert_eq!(targets[0].ticket.as_ref().unwrap().sys_id, "sys1");
+    assert!(targets[1].ticket.is_none());
+
+    let unknown = resolve_split(
+        &backend,
+        &default_categories(),
+        "RITM9999999=1h,clerical=2h",
+        "3h",
+        "work",
+        None,
+    )
+    .await
+    .unwrap_err();
+    assert!(unknown
+        .to_string()
+        .contains("RITM9999999 is neither a category nor a ticket"));
+
+    backend.set_offline(true);
+    assert!(resolve_split(
+        &backend,
+        &default_categories(),
+        "RITM0000001=1h,clerical=2h",
+        "3h",
+        "work",
+        None,
+    )
+    .await
+    .unwrap_err()
+    .is_transient());
+    backend.set_offline(false);
+
+    track_split(&backend, &entries).await.unwrap();
+    assert_eq!(backend.time_entries.lock().unwrap().len(), 2);
+}
+
+#[tokio::test]
+async fn test_import_rows_are_validated_up_front() {
+    use elasticnow::cli::import::{read_rows, validate_rows, ImportRow};
+    let path = std::env::temp_dir().join(format!("elasticnow-import-{}.csv", std::process::id()));
+    std::fs::write(
+        &path,
+        "Date,Ticket,Duration,Comment\n\
+         2024-06-24,RITM0000001,1h30m,\"Patching, part 1\"\n\
+         2024-6-25 , clerical , 15m , Email\n",
+    )
+    .unwrap();
+    let rows = read_rows(&path).unwrap();
+    let _ = std::fs::remove_file(&path);
+    assert_eq!(rows[0].comment, "Patching, part 1");
+    assert_eq!(rows[1].target, "clerical");
+
+    let backend = backend();
+    let validated = validate_rows(&backend, &default_categories(), &rows)
+        .await
+        .unwrap();
+    let valid = validated[0].as_ref().unwrap();
+    assert_eq!(valid.entry.target, TimeTarget::Ticket("sys1".to_string()));
+    assert_eq!(valid.label, "RITM0000001: Patch web servers");
+    assert_eq!(valid.seconds, 5400);
+    let valid = validated[1].as_ref().unwrap();
+    assert_eq!(
+        valid.entry.target,
+        TimeTarget::Category("clerical".to_string())
+    );
+    assert_eq!(valid.entry.date.as_deref(), Some("2024-06-25"));
+
+    let row = |date: &str, target: &str, duration: &str, comment: &str| ImportRow {
+        date: date.to_string(),
+        target: target.to_string(),
+        duration: duration.to_string(),
+        comment: comment.to_string(),
+    };
+    let invalid = vec![
+        row("24/06/2024", "clerical", "1h", "work"),
+        row("2024-06-24", "clerical", "soon", "work"),
+        row("2024-06-24", "clerical", "9999999999h", "work"),
+        row("2024-06-24", "RITM9999999", "1h", "work"),
+        row("2024-06-24", "RITM0000001", "1h", ""),
+    ];
+    let validated = validate_rows(&backend, &default_categories(), &invalid)
+        .await
+        .unwrap();
+    assert!(validated.iter().all(|v| v.is_err()));
+    assert!(validated[2]
+        .as_ref()
+        .unwrap_err()
+        .contains("longer than the maximum"));
+    assert!(validated[3]
+        .as_ref()
+        .unwrap_err()
+        .contains("neither a category nor a ticket"));
+
+    backend.set_offline(true);
+    assert!(validate_rows(&backend, &default_categories(), &rows[..1])
+        .await
+        .unwrap_err()
+        .is_transient());
+    assert!(backend.time_entries.lock().unwrap().is_empty());
+}